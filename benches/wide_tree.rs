@@ -0,0 +1,55 @@
+//! Traverses a tree shaped like a `node_modules` directory, with thousands of sibling packages, with different
+//! numbers of worker threads. Almost all directories are found by the worker that reads `node_modules`, so the
+//! others depend on stealing from it.
+//!
+//! Run with `cargo bench --bench wide_tree`.
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+
+const PACKAGES: usize = 2000;
+
+fn make_package(dir: &Path, nested: usize) -> io::Result<()> {
+    fs::create_dir_all(dir.join("lib"))?;
+    File::create(dir.join("package.json"))?;
+    File::create(dir.join("index.js"))?;
+    for i in 0..4 {
+        File::create(dir.join("lib").join(format!("{}.js", i)))?;
+    }
+    for i in 0..nested {
+        make_package(&dir.join("node_modules").join(format!("dep{}", i)), 0)?;
+    }
+    Ok(())
+}
+
+fn make_tree() -> io::Result<tempfile::TempDir> {
+    let root = tempfile::Builder::new().prefix("bfind").tempdir()?;
+    for i in 0..PACKAGES {
+        make_package(&root.path().join("node_modules").join(format!("package{}", i)), i % 3)?;
+    }
+    Ok(root)
+}
+
+fn wide_tree(c: &mut Criterion) {
+    let root = make_tree().expect("failed to create the tree");
+    let mut group = c.benchmark_group("wide_tree");
+    group.sample_size(20);
+    for threads in [1, 2, 4, 8] {
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, &threads| {
+            b.iter(|| {
+                bfind::WalkBuilder::new(root.path()).threads(threads).build().unwrap().count()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, wide_tree);
+criterion_main!(benches);
@@ -0,0 +1,30 @@
+//! Values computed in the worker threads and carried with the entries to the printer, so that the main thread only
+//! writes them out. Each is computed the first time it is needed, by the expression or by the last filter of the
+//! walker.
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use bfind::DirEntry;
+
+use crate::hash::Digests;
+
+#[derive(Default)]
+pub struct Attached {
+    pub digests:    OnceLock<io::Result<Digests>>,
+
+    /// The columns of `--long` before the path.
+    pub long:       OnceLock<[String; 6]>,
+
+    /// The target of a symbolic link, `None` if the entry is not one or the link cannot be read.
+    pub target:     OnceLock<Option<PathBuf>>,
+
+    /// The styles of a symbolic link and of its target with `--color`.
+    pub link_styles: OnceLock<[Option<String>; 2]>,
+}
+
+/// The values attached to an entry.
+pub fn of(entry: &DirEntry) -> &Attached {
+    entry.extension(Attached::default).expect("only `Attached` is attached to entries")
+}
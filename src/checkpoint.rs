@@ -0,0 +1,116 @@
+//! The file written by [`Paused::checkpoint`](crate::Paused::checkpoint).
+//!
+//! It starts with a header holding the roots of the traversal, the number of entries yielded so far and, when
+//! following symbolic links, the directories already seen, followed by the queued directories in the format of spill
+//! files.
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::path_queue;
+
+const MAGIC: &[u8; 8] = b"BFINDCP\x01";
+
+/// Longest root a checkpoint holds, well above the longest path the system accepts, so that a corrupt length does not
+/// allocate gigabytes.
+const MAX_ROOT_LEN: usize = 1 << 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Header {
+    pub yielded:    u64,
+    pub roots:      Vec<PathBuf>,
+    pub visited:    Vec<(u64, u64)>,
+}
+
+pub(crate) fn write_header<W: Write>(w: &mut W, header: &Header) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&header.yielded.to_le_bytes())?;
+    w.write_all(&(header.roots.len() as u32).to_le_bytes())?;
+    for root in &header.roots {
+        let bytes = root.as_os_str().as_encoded_bytes();
+        if bytes.len() > MAX_ROOT_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "root too long for a checkpoint"));
+        }
+        w.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        w.write_all(bytes)?;
+    }
+    w.write_all(&(header.visited.len() as u64).to_le_bytes())?;
+    for (dev, ino) in &header.visited {
+        w.write_all(&dev.to_le_bytes())?;
+        w.write_all(&ino.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Reads the header, failing with [`io::ErrorKind::InvalidData`] if the file is not a checkpoint.
+pub(crate) fn read_header<R: Read>(r: &mut R) -> io::Result<Header> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a bfind checkpoint");
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid());
+    }
+    let yielded = read_u64(r)?;
+    let mut roots = Vec::new();
+    for _ in 0..read_u32(r)? {
+        let len = read_u32(r)? as usize;
+        if len > MAX_ROOT_LEN {
+            return Err(invalid());
+        }
+        let mut bytes = vec![0u8; len];
+        r.read_exact(&mut bytes)?;
+        roots.push(path_queue::path_from_bytes(&bytes).map_err(|_| invalid())?);
+    }
+    let mut visited = Vec::new();
+    for _ in 0..read_u64(r)? {
+        visited.push((read_u64(r)?, read_u64(r)?));
+    }
+    Ok(Header { yielded, roots, visited })
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_roundtrip() -> io::Result<()> {
+        let header = Header {
+            yielded: 12345,
+            roots: vec![PathBuf::from("."), PathBuf::from("/a/b")],
+            visited: vec![(1, 2), (3, u64::MAX)],
+        };
+        let mut buf = Vec::new();
+        write_header(&mut buf, &header)?;
+        assert_eq!(read_header(&mut &buf[..])?, header);
+        buf[0] = b'X';
+        assert_eq!(read_header(&mut &buf[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_lengths() -> io::Result<()> {
+        let mut buf = Vec::new();
+        write_header(&mut buf, &Header { yielded: 0, roots: vec![PathBuf::from(".")], visited: Vec::new() })?;
+        // The length of the first root follows the magic, the yielded count and the number of roots.
+        buf[20..24].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(read_header(&mut &buf[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let long = Header { yielded: 0, roots: vec![PathBuf::from("a".repeat(MAX_ROOT_LEN + 1))], visited: Vec::new() };
+        assert_eq!(write_header(&mut Vec::new(), &long).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+}
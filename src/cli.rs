@@ -0,0 +1,640 @@
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Opt {
+    Hidden,
+    FollowLinks,
+    Depth,
+    Ignore,
+    OneFileSystem,
+    StripCwdPrefix,
+    Threads,
+    Memory,
+    SpillDir,
+    MaxSpill,
+    NoSpill,
+    SpillCompression,
+    Color,
+    Json,
+    Long,
+    Csv,
+    Tsv,
+    Columns,
+    Binary,
+    MaxContentSize,
+    Tree,
+    TreeTotals,
+    Progress,
+    Stats,
+    StatsJson,
+    Checkpoint,
+    CheckpointInterval,
+    Resume,
+    NoConfig,
+    PrintConfig,
+    Help,
+    Man,
+}
+
+pub struct OptionSpec {
+    pub opt:            Opt,
+    pub short:          Option<&'static str>,
+    pub long:           &'static str,
+    pub arg:            Option<&'static str>,
+    pub help:           &'static str,
+
+    /// Whether the option may be set in the configuration file and in `BFIND_OPTS`.
+    pub configurable:   bool,
+}
+
+impl OptionSpec {
+    pub fn matches(&self, arg: &str) -> bool {
+        self.short == Some(arg) || self.long == arg
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.short.unwrap_or(self.long)
+    }
+
+    /// The key of the option in the configuration file, i.e. the long name without the dashes.
+    pub fn config_key(&self) -> &'static str {
+        self.long.trim_start_matches('-')
+    }
+}
+
+pub const OPTIONS: &[OptionSpec] = &[
+    OptionSpec {
+        opt: Opt::Hidden,
+        short: Some("-H"),
+        long: "--hidden",
+        arg: None,
+        help: "Include hidden files and directories, i.e. those whose names start with a dot.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::FollowLinks,
+        short: Some("-L"),
+        long: "--follow",
+        arg: None,
+        help: "Follow symbolic links.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::Depth,
+        short: Some("-d"),
+        long: "--depth",
+        arg: Some("DEPTH"),
+        help: "Descend at most DEPTH levels below the starting directories. DEPTH must be greater than 0.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::Ignore,
+        short: Some("-I"),
+        long: "--ignore",
+        arg: Some("NAMES"),
+        help: "Skip files and directories whose name is in the comma-separated list NAMES.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::OneFileSystem,
+        short: Some("-x"),
+        long: "--one-file-system",
+        arg: None,
+        help: "Do not descend into directories on other file systems than the starting directory.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::StripCwdPrefix,
+        short: None,
+        long: "--strip-cwd-prefix",
+        arg: None,
+        help: "Print paths under the current working directory relative to it, without the leading \"./\".",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::Threads,
+        short: Some("-j"),
+        long: "--threads",
+        arg: Some("NUM"),
+        help: "Use NUM worker threads. The default is the number of available CPUs plus one.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::Memory,
+        short: None,
+        long: "--memory",
+        arg: Some("SIZE"),
+        help: "Keep at most SIZE of directories waiting to be read in memory and spill the rest to disk, e.g. 256MiB.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::SpillDir,
+        short: None,
+        long: "--spill-dir",
+        arg: Some("DIR"),
+        help: "Create the files that directories waiting to be read are spilled to in DIR instead of the temporary directory.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::MaxSpill,
+        short: None,
+        long: "--max-spill",
+        arg: Some("SIZE"),
+        help: "Fail instead of writing more than SIZE to spill files, e.g. 512MiB.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::NoSpill,
+        short: None,
+        long: "--no-spill",
+        arg: None,
+        help: "Keep all directories waiting to be read in memory instead of spilling them to disk.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::SpillCompression,
+        short: None,
+        long: "--spill-compression",
+        arg: Some("METHOD"),
+        help: "Compress directories queued on disk with METHOD, one of none, lz4 or zstd. The default is lz4 if it is compiled in.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::Color,
+        short: None,
+        long: "--color",
+        arg: Some("WHEN"),
+        help: "Color paths according to LS_COLORS: auto, always or never. auto colors only if stdout is a terminal and NO_COLOR is not set. The directories leading to each entry are dim.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::Json,
+        short: None,
+        long: "--json",
+        arg: None,
+        help: "Print one JSON object per line for each entry, with its path, name, depth, type, size, mode, uid, gid, atime, mtime, ctime, symbolic link target and root index. Paths that are not valid UTF-8 are printed as {\"bytes\": BASE64}.",
+        configurable: false,
+    },
+    OptionSpec {
+        opt: Opt::Long,
+        short: Some("-l"),
+        long: "--long",
+        arg: None,
+        help: "Print a long listing like ls -l: type and permissions, link count, owner, group, human-readable size, modification time, path and symbolic link target.",
+        configurable: false,
+    },
+    OptionSpec {
+        opt: Opt::Csv,
+        short: None,
+        long: "--csv",
+        arg: None,
+        help: "Print the entries as comma-separated values, with a header row naming the columns.",
+        configurable: false,
+    },
+    OptionSpec {
+        opt: Opt::Tsv,
+        short: None,
+        long: "--tsv",
+        arg: None,
+        help: "Print the entries as tab-separated values, with a header row naming the columns. Tabs, newlines and backslashes in values are escaped with backslashes.",
+        configurable: false,
+    },
+    OptionSpec {
+        opt: Opt::Columns,
+        short: None,
+        long: "--columns",
+        arg: Some("LIST"),
+        help: "Print the comma-separated LIST of fields, named as the placeholders of print, as the columns of --csv and --tsv. The default is path,type,size,mtime.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::Binary,
+        short: None,
+        long: "--binary",
+        arg: None,
+        help: "Let content predicates match binary files too, which are files with a NUL byte in their first 8 KiB.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::MaxContentSize,
+        short: None,
+        long: "--max-content-size",
+        arg: Some("SIZE"),
+        help: "Content predicates never match files larger than SIZE. The default is 16MiB.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::Tree,
+        short: None,
+        long: "--tree",
+        arg: None,
+        help: "Collect the entries and print them at the end as a tree under each starting directory, sorted by name.",
+        configurable: false,
+    },
+    OptionSpec {
+        opt: Opt::TreeTotals,
+        short: None,
+        long: "--tree-totals",
+        arg: None,
+        help: "Like --tree, and print the number of entries below each directory and their total size.",
+        configurable: false,
+    },
+    OptionSpec {
+        opt: Opt::Progress,
+        short: None,
+        long: "--progress",
+        arg: None,
+        help: "Report the directories and entries seen, matches, depth, queued directories and throughput on stderr, redrawn in place on a terminal and as one line per second otherwise.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::Stats,
+        short: None,
+        long: "--stats",
+        arg: None,
+        help: "Print statistics of the traversal on stderr at the end: entries, directories, errors by kind, maximum depth, steals between threads, bytes spilled, the time each thread spent working and sleeping, and wall and CPU time.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::StatsJson,
+        short: None,
+        long: "--stats-json",
+        arg: None,
+        help: "Like --stats, but print the statistics as a JSON object.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::Checkpoint,
+        short: None,
+        long: "--checkpoint",
+        arg: Some("FILE"),
+        help: "Periodically save the directories waiting to be read and the number of entries printed to FILE, and once more at the end. The output is held back until the next checkpoint, so that an interrupted run resumed with --resume prints every entry once. Commands of exec since the last checkpoint run again when resuming.",
+        configurable: false,
+    },
+    OptionSpec {
+        opt: Opt::CheckpointInterval,
+        short: None,
+        long: "--checkpoint-interval",
+        arg: Some("SECONDS"),
+        help: "Save a checkpoint every SECONDS seconds. The default is 60.",
+        configurable: true,
+    },
+    OptionSpec {
+        opt: Opt::Resume,
+        short: None,
+        long: "--resume",
+        arg: Some("FILE"),
+        help: "Continue the traversal saved to FILE by --checkpoint, without printing the entries printed before the checkpoint again. The same roots must be given.",
+        configurable: false,
+    },
+    OptionSpec {
+        opt: Opt::NoConfig,
+        short: None,
+        long: "--no-config",
+        arg: None,
+        help: "Ignore the configuration file and the BFIND_OPTS environment variable.",
+        configurable: false,
+    },
+    OptionSpec {
+        opt: Opt::PrintConfig,
+        short: None,
+        long: "--print-config",
+        arg: None,
+        help: "Print the effective settings in configuration file format and exit.",
+        configurable: false,
+    },
+    OptionSpec {
+        opt: Opt::Help,
+        short: Some("-h"),
+        long: "--help",
+        arg: None,
+        help: "Print a short usage summary and exit.",
+        configurable: false,
+    },
+    OptionSpec {
+        opt: Opt::Man,
+        short: None,
+        long: "--man",
+        arg: None,
+        help: "Print this manual page in roff format and exit.",
+        configurable: false,
+    },
+];
+
+pub fn find_option(arg: &str) -> Option<&'static OptionSpec> {
+    OPTIONS.iter().find(|spec| spec.matches(arg))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verb {
+    Print,
+    Exec,
+    Du,
+    Dupes,
+}
+
+pub struct VerbSpec {
+    pub verb:   Verb,
+    pub name:   &'static str,
+    pub args:   &'static str,
+    pub help:   &'static str,
+}
+
+pub const VERBS: &[VerbSpec] = &[
+    VerbSpec {
+        verb: Verb::Print,
+        name: "print",
+        args: "[FORMAT]",
+        help: "Print each matching entry, formatted with FORMAT if given. This is the default.",
+    },
+    VerbSpec {
+        verb: Verb::Exec,
+        name: "exec",
+        args: "COMMAND [ARG ...]",
+        help: "Execute COMMAND for each matching entry and wait for it, instead of printing the entry. Format placeholders are expanded in COMMAND and each ARG.",
+    },
+    VerbSpec {
+        verb: Verb::Du,
+        name: "du",
+        args: "[N]",
+        help: "Add up the sizes of the matching entries per directory, counting hard links once, and print the N largest directories at each depth by size on disk, then apparent size. N defaults to 10.",
+    },
+    VerbSpec {
+        verb: Verb::Dupes,
+        name: "dupes",
+        args: "",
+        help: "Print the groups of matching files with the same content, compared by size, then by a BLAKE3 hash of their first 4 KiB, then of all of it. Empty files and further hard links to a file are left out. With --json, print one object per group with its size, hash and paths.",
+    },
+];
+
+pub fn find_verb(arg: &str) -> Option<&'static VerbSpec> {
+    VERBS.iter().find(|spec| spec.name == arg)
+}
+
+pub struct SyntaxSpec {
+    pub syntax: &'static str,
+    pub help:   &'static str,
+}
+
+pub const EXPR_SYNTAX: &[SyntaxSpec] = &[
+    SyntaxSpec { syntax: "EXPR and EXPR", help: "True if both expressions are true." },
+    SyntaxSpec { syntax: "EXPR or EXPR", help: "True if either expression is true." },
+    SyntaxSpec { syntax: "not EXPR", help: "True if the expression is false." },
+    SyntaxSpec { syntax: "( EXPR )", help: "Grouping." },
+];
+
+pub const PREDICATES: &[SyntaxSpec] = &[
+    SyntaxSpec { syntax: "name match REGEX", help: "The file name matches the regular expression REGEX." },
+    SyntaxSpec { syntax: "name glob PATTERN", help: "The file name matches the shell glob PATTERN." },
+    SyntaxSpec { syntax: "type is TYPE", help: "The file is of type TYPE, one of file, dir, link, fifo, socket, block or char." },
+    SyntaxSpec { syntax: "size gt SIZE", help: "The file is larger than SIZE, e.g. 1MiB." },
+    SyntaxSpec { syntax: "content match REGEX", help: "The file is a regular file whose contents match the regular expression REGEX. Binary files and files larger than --max-content-size never match." },
+    SyntaxSpec { syntax: "content contains STRING", help: "The file is a regular file whose contents contain STRING, with the same limits as content match." },
+    SyntaxSpec { syntax: "hash eq [ALGORITHM:]HEX", help: "The file is a regular file whose contents hash to HEX with ALGORITHM, one of sha256, blake3 or md5. Without ALGORITHM, 32 digits are an MD5 hash and 64 digits a SHA-256 hash." },
+    SyntaxSpec { syntax: "perm has MODE", help: "All the permission bits in MODE are set. MODE is octal, like 4000, or symbolic, like u+x,g+w, where s applies to u and g, and t to o." },
+    SyntaxSpec { syntax: "perm eq MODE", help: "The permission bits, including setuid, setgid and sticky, are exactly MODE, e.g. 644." },
+    SyntaxSpec { syntax: "perm any MODE", help: "Any of the permission bits in MODE is set, e.g. u+x,g+x,o+x." },
+    SyntaxSpec { syntax: "user is USER", help: "The file is owned by USER, a user name or numeric ID." },
+    SyntaxSpec { syntax: "group is GROUP", help: "The file belongs to GROUP, a group name or numeric ID." },
+    SyntaxSpec { syntax: "nouser", help: "The owner of the file has no user name." },
+    SyntaxSpec { syntax: "nogroup", help: "The group of the file has no name." },
+    SyntaxSpec { syntax: "readable", help: "The file is readable by the user running bfind, as checked by access(2)." },
+    SyntaxSpec { syntax: "writable", help: "The file is writable by the user running bfind, as checked by access(2)." },
+    SyntaxSpec { syntax: "executable", help: "The file is executable, or the directory searchable, by the user running bfind, as checked by access(2)." },
+];
+
+pub const PLACEHOLDERS: &[SyntaxSpec] = &[
+    SyntaxSpec { syntax: "{path}", help: "The path of the file as printed without a template." },
+    SyntaxSpec { syntax: "{fullpath}", help: "The path of the file, including the starting directory." },
+    SyntaxSpec { syntax: "{name}", help: "The file name." },
+    SyntaxSpec { syntax: "{depth}", help: "The depth of the file below its starting directory, which is 1 for the entries in it." },
+    SyntaxSpec { syntax: "{root}", help: "The index of the starting directory of the file, from 0." },
+    SyntaxSpec { syntax: "{type}", help: "The type of the file: file, dir, link, fifo, socket, block, char or unknown." },
+    SyntaxSpec { syntax: "{size}", help: "The size of the file in bytes." },
+    SyntaxSpec { syntax: "{mode}", help: "The permission bits of the file in octal." },
+    SyntaxSpec { syntax: "{uid}", help: "The numeric user ID of the owner of the file." },
+    SyntaxSpec { syntax: "{gid}", help: "The numeric group ID of the file." },
+    SyntaxSpec { syntax: "{atime}", help: "The last access time of the file, in seconds since the Unix epoch." },
+    SyntaxSpec { syntax: "{mtime}", help: "The last modification time of the file, in seconds since the Unix epoch." },
+    SyntaxSpec { syntax: "{ctime}", help: "The last status change time of the file, in seconds since the Unix epoch." },
+    SyntaxSpec { syntax: "{ino}", help: "The inode number of the file." },
+    SyntaxSpec { syntax: "{dev}", help: "The device number of the file system the file is on." },
+    SyntaxSpec { syntax: "{target}", help: "The target of a symbolic link, empty for other files." },
+    SyntaxSpec { syntax: "{sha256}", help: "The SHA-256 hash of the contents of a regular file in hexadecimal, empty for other files. Hashes are computed in the worker threads, only for the entries that are printed." },
+    SyntaxSpec { syntax: "{blake3}", help: "The BLAKE3 hash of the contents of a regular file, like {sha256}." },
+    SyntaxSpec { syntax: "{md5}", help: "The MD5 hash of the contents of a regular file, like {sha256}." },
+];
+
+/// The exit statuses of bfind, which `main` passes to `exit` and the man page lists from [`EXIT_CODES`].
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+pub const EXIT_CODES: &[(i32, &str)] = &[
+    (EXIT_SUCCESS, "Success, including when the output is closed early. Entries that could not be read are reported without changing the status."),
+    (EXIT_FAILURE, "A fatal error: the traversal could not start or resume, a queue could not spill to disk, or the output could not be written. Also when a command of exec could not be run or failed; the traversal goes on."),
+    (EXIT_USAGE, "Invalid command line, configuration file or BFIND_OPTS, including the expression and the FORMAT."),
+];
+
+/// Parses a size such as `1024`, `10k`, `1.5MiB` or `2GB`. Suffixes with an `i`, and single letters, are binary;
+/// `kB`, `MB` and so on are decimal.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, suffix) = s.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let multiplier: u64 = match suffix.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "kb" => 1000,
+        "mb" => 1000 * 1000,
+        "gb" => 1000 * 1000 * 1000,
+        "tb" => 1000 * 1000 * 1000 * 1000,
+        _ => return None,
+    };
+    Some((number * multiplier as f64) as u64)
+}
+
+pub fn usage(prog: &str) -> String {
+    let mut options = String::new();
+    for spec in OPTIONS {
+        if spec.short.is_some() {
+            write!(options, " [{}", spec.name()).unwrap();
+            if let Some(arg) = spec.arg {
+                write!(options, " {}", arg).unwrap();
+            }
+            options.push(']');
+        }
+    }
+    format!("{}:{} [DIR ...] [VERB ...] [-- EXPR ...]", prog, options)
+}
+
+pub fn help(prog: &str) -> String {
+    let mut s = usage(prog);
+    s.push_str("\n\nOptions:\n");
+    for spec in OPTIONS {
+        let mut names = String::new();
+        if let Some(short) = spec.short {
+            write!(names, "{}, ", short).unwrap();
+        }
+        names.push_str(spec.long);
+        if let Some(arg) = spec.arg {
+            write!(names, " {}", arg).unwrap();
+        }
+        writeln!(s, "  {:<24}  {}", names, spec.help).unwrap();
+    }
+    s.push_str("\nVerbs:\n");
+    for spec in VERBS {
+        writeln!(s, "  {:<24}  {}", format!("{} {}", spec.name, spec.args), spec.help).unwrap();
+    }
+    s.push_str("\nRun with --man for the full manual.");
+    s
+}
+
+fn roff_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\e"),
+            '-' => escaped.push_str("\\-"),
+            _ => escaped.push(c),
+        }
+    }
+    if escaped.starts_with('.') || escaped.starts_with('\'') {
+        escaped.insert_str(0, "\\&");
+    }
+    escaped
+}
+
+fn roff_items(s: &mut String, items: &[SyntaxSpec]) {
+    for item in items {
+        writeln!(s, ".TP\n.B {}\n{}", roff_escape(item.syntax), roff_escape(item.help)).unwrap();
+    }
+}
+
+pub fn man_page() -> String {
+    let mut s = String::new();
+    writeln!(s, ".TH BFIND 1 \"\" \"bfind {}\" \"User Commands\"", env!("CARGO_PKG_VERSION")).unwrap();
+
+    s.push_str(".SH NAME\n");
+    writeln!(s, "bfind \\- {}", roff_escape(env!("CARGO_PKG_DESCRIPTION"))).unwrap();
+
+    s.push_str(".SH SYNOPSIS\n.B bfind\n");
+    s.push_str("[\\fIOPTION\\fR ...] [\\fIDIR\\fR ...] [\\fIVERB\\fR ...] [\\fB\\-\\-\\fR \\fIEXPR\\fR ...]\n");
+
+    s.push_str(".SH DESCRIPTION\n");
+    s.push_str("\\fBbfind\\fR walks each \\fIDIR\\fR (the current directory if none is given) breadth-first, ");
+    s.push_str("so entries in shallower directories are listed before deeper ones. ");
+    s.push_str("Directories are read by a pool of worker threads.\n");
+
+    s.push_str(".SH OPTIONS\n");
+    for spec in OPTIONS {
+        s.push_str(".TP\n");
+        let mut names = Vec::new();
+        for name in spec.short.iter().chain(std::iter::once(&spec.long)) {
+            let mut name = format!("\\fB{}\\fR", roff_escape(name));
+            if let Some(arg) = spec.arg {
+                write!(name, " \\fI{}\\fR", arg).unwrap();
+            }
+            names.push(name);
+        }
+        writeln!(s, "{}\n{}", names.join(", "), roff_escape(spec.help)).unwrap();
+    }
+
+    s.push_str(".SH VERBS\n");
+    for spec in VERBS {
+        writeln!(s, ".TP\n\\fB{}\\fR \\fI{}\\fR\n{}", spec.name, roff_escape(spec.args), roff_escape(spec.help)).unwrap();
+    }
+
+    s.push_str(".SH EXPRESSIONS\n");
+    s.push_str("Everything after \\fB\\-\\-\\fR is an expression that selects the entries the verb is applied to.\n");
+    roff_items(&mut s, EXPR_SYNTAX);
+    s.push_str(".PP\nPredicates:\n");
+    roff_items(&mut s, PREDICATES);
+
+    s.push_str(".SH FORMAT\n");
+    s.push_str("The \\fIFORMAT\\fR of \\fBprint\\fR and the arguments of \\fBexec\\fR may contain placeholders. ");
+    s.push_str("A placeholder may carry a width and alignment, e.g. \\fB{size:>10}\\fR.\n");
+    roff_items(&mut s, PLACEHOLDERS);
+
+    s.push_str(".SH ENVIRONMENT\n");
+    s.push_str(".TP\n.B BFIND_OPTS\n");
+    s.push_str("Whitespace-separated options applied after the configuration file and before the command line. ");
+    s.push_str("Single and double quotes group words.\n");
+    s.push_str(".TP\n.B XDG_CONFIG_HOME\n");
+    s.push_str("The directory containing \\fBbfind/config.toml\\fR. Defaults to \\fB~/.config\\fR.\n");
+
+    s.push_str(".SH FILES\n");
+    s.push_str(".TP\n.B $XDG_CONFIG_HOME/bfind/config.toml\n");
+    s.push_str("Default settings, as a TOML table whose keys are long option names without the leading dashes. ");
+    s.push_str("Flags take a boolean, other options a string or an integer; \\fBignore\\fR also takes an array of strings. ");
+    s.push_str("Options given on the command line override the file. Keys accepted:");
+    for spec in OPTIONS.iter().filter(|spec| spec.configurable) {
+        write!(s, " \\fB{}\\fR", roff_escape(spec.config_key())).unwrap();
+    }
+    s.push_str(".\n");
+
+    s.push_str(".SH EXIT STATUS\n");
+    for (code, help) in EXIT_CODES {
+        writeln!(s, ".TP\n.B {}\n{}", code, roff_escape(help)).unwrap();
+    }
+
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_are_unique() {
+        for (i, a) in OPTIONS.iter().enumerate() {
+            for b in &OPTIONS[i + 1..] {
+                assert_ne!(a.opt, b.opt);
+                assert!(!b.matches(a.long));
+                if let Some(short) = a.short {
+                    assert!(!b.matches(short));
+                }
+            }
+        }
+        for (i, (a, _)) in EXIT_CODES.iter().enumerate() {
+            assert!(EXIT_CODES[i + 1..].iter().all(|(b, _)| a != b));
+        }
+    }
+
+    #[test]
+    fn man_page_documents_everything() {
+        let man = man_page();
+        for spec in OPTIONS {
+            assert!(man.contains(&roff_escape(spec.long)), "{} is not documented", spec.long);
+        }
+        for spec in VERBS {
+            assert!(man.contains(&format!("\\fB{}\\fR", spec.name)), "{} is not documented", spec.name);
+        }
+        for item in PREDICATES.iter().chain(PLACEHOLDERS) {
+            assert!(man.contains(&roff_escape(item.syntax)), "{} is not documented", item.syntax);
+        }
+        for (code, _) in EXIT_CODES {
+            assert!(man.contains(&format!(".B {}\n", code)));
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size("10k"), Some(10240));
+        assert_eq!(parse_size("1.5MiB"), Some(3 << 19));
+        assert_eq!(parse_size("2GB"), Some(2_000_000_000));
+        assert_eq!(parse_size("1x"), None);
+        assert_eq!(parse_size("MiB"), None);
+    }
+
+    #[test]
+    fn roff_escapes_dashes_and_control_lines() {
+        assert_eq!(roff_escape("--help"), "\\-\\-help");
+        assert_eq!(roff_escape(".hidden"), "\\&.hidden");
+        assert_eq!(roff_escape("a\\b"), "a\\eb");
+    }
+}
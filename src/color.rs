@@ -0,0 +1,300 @@
+//! Coloring of paths with `--color`, following `LS_COLORS` like `ls`. The file name is colored by type, permissions
+//! or extension, and the directories before it are dim.
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::Path;
+
+use bfind::DirEntry;
+
+use crate::attached;
+use crate::fields;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum When {
+    Auto,
+    Always,
+    Never,
+}
+
+impl When {
+    pub fn parse(s: &str) -> Option<When> {
+        match s {
+            "auto" => Some(When::Auto),
+            "always" => Some(When::Always),
+            "never" => Some(When::Never),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            When::Auto => "auto",
+            When::Always => "always",
+            When::Never => "never",
+        }
+    }
+
+    /// Whether to color, in auto mode only if stdout is a terminal and neither `NO_COLOR` nor `TERM=dumb` are set.
+    pub fn enabled(self) -> bool {
+        match self {
+            When::Always => true,
+            When::Never => false,
+            When::Auto => {
+                std::io::stdout().is_terminal()
+                    && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && env::var_os("TERM").is_none_or(|term| term != "dumb")
+            },
+        }
+    }
+}
+
+/// The styles `dircolors` uses when `LS_COLORS` is not set.
+const DEFAULT_LS_COLORS: &str = "di=01;34:ln=01;36:pi=40;33:so=01;35:do=01;35:bd=40;33;01:cd=40;33;01:or=40;31;01:\
+                                 su=37;41:sg=30;43:tw=30;42:ow=34;42:st=37;44:ex=01;32";
+
+/// Style of the directories before the file name.
+const DIM: &str = "2";
+
+pub struct Colors {
+    /// Styles by the two-letter keys of `LS_COLORS`, like `di` for directories.
+    types:      HashMap<String, String>,
+
+    /// Styles by file name suffix, in lower case, from the `*.ext` entries of `LS_COLORS`.
+    suffixes:   Vec<(String, String)>,
+}
+
+impl Colors {
+    /// Reads `LS_COLORS`, or uses the defaults of `dircolors` if it is not set.
+    pub fn from_env() -> Self {
+        match env::var("LS_COLORS") {
+            Ok(s) if !s.is_empty() => Self::parse(&s),
+            _ => Self::parse(DEFAULT_LS_COLORS),
+        }
+    }
+
+    pub fn parse(s: &str) -> Self {
+        let mut colors = Colors { types: HashMap::new(), suffixes: Vec::new() };
+        for item in s.split(':') {
+            let Some((key, style)) = item.split_once('=') else {
+                continue;
+            };
+            if let Some(suffix) = key.strip_prefix('*') {
+                colors.suffixes.push((suffix.to_lowercase(), style.to_string()));
+            } else {
+                colors.types.insert(key.to_string(), style.to_string());
+            }
+        }
+        // Later entries win, as in ls.
+        colors.suffixes.reverse();
+        colors
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.types.get(key).map(String::as_str).filter(|style| !style.is_empty() && *style != "0")
+    }
+
+    /// The key of the style of a file of type `file_type` and with `metadata`, if known.
+    fn type_key(file_type: fs::FileType, metadata: Option<&fs::Metadata>) -> Option<&'static str> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            let mode = metadata.map_or(0, |m| m.mode());
+            if file_type.is_dir() {
+                return Some(match (mode & 0o1000 != 0, mode & 0o002 != 0) {
+                    (true, true) => "tw",
+                    (false, true) => "ow",
+                    (true, false) => "st",
+                    (false, false) => "di",
+                });
+            } else if file_type.is_fifo() {
+                return Some("pi");
+            } else if file_type.is_socket() {
+                return Some("so");
+            } else if file_type.is_block_device() {
+                return Some("bd");
+            } else if file_type.is_char_device() {
+                return Some("cd");
+            } else if file_type.is_file() && mode & 0o4000 != 0 {
+                return Some("su");
+            } else if file_type.is_file() && mode & 0o2000 != 0 {
+                return Some("sg");
+            } else if file_type.is_file() && mode & 0o111 != 0 {
+                return Some("ex");
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = metadata;
+        if file_type.is_dir() {
+            Some("di")
+        } else {
+            None
+        }
+    }
+
+    /// The style of the name of a file, by type, permissions, then suffix.
+    fn style(&self, name: &str, file_type: fs::FileType, metadata: Option<&fs::Metadata>) -> Option<&str> {
+        let special = Self::type_key(file_type, metadata).and_then(|key| {
+            // Directories with special permissions fall back to the directory style.
+            self.get(key).or_else(|| if file_type.is_dir() { self.get("di") } else { None })
+        });
+        if special.is_some() {
+            return special;
+        }
+        let name = name.to_lowercase();
+        self.suffixes.iter()
+            .find(|(suffix, _)| name.ends_with(suffix.as_str()))
+            .map(|(_, style)| style.as_str())
+            .or_else(|| self.get("fi"))
+    }
+
+    /// Appends `shown`, the path of `entry` as printed, to `out` with the directories dim and the name colored.
+    /// `metadata` is that of the entry, or of its target if the walker follows symbolic links.
+    pub fn paint(&self, out: &mut String, entry: &DirEntry, shown: &Path, metadata: Option<&fs::Metadata>) {
+        let shown = shown.to_string_lossy();
+        let name = entry.file_name().to_string_lossy();
+        let (dir, name) = match shown.strip_suffix(name.as_ref()) {
+            Some(dir) => (dir, &shown[dir.len()..]),
+            None => ("", shown.as_ref()),
+        };
+        if !dir.is_empty() {
+            push_styled(out, DIM, dir);
+        }
+        let style = if entry.is_symlink() && entry.file_type().is_symlink() {
+            self.prefetch(entry)[0].as_deref()
+        } else {
+            self.style(name, entry.file_type(), metadata)
+        };
+        match style {
+            Some(style) => push_styled(out, style, name),
+            None => out.push_str(name),
+        }
+    }
+
+    /// Looks up the styles of a symbolic link and of its target, which need the metadata of the target, and attaches
+    /// them to the entry. Called by a filter of the walker, so that the targets are stat'ed in the worker threads.
+    ///
+    /// A link that is not followed is styled `or` if its target is missing, like its target if `ln` is `target`, and
+    /// `ln` otherwise. The target is styled as a file of its own.
+    pub fn prefetch<'e>(&self, entry: &'e DirEntry) -> &'e [Option<String>; 2] {
+        attached::of(entry).link_styles.get_or_init(|| {
+            let target = fs::metadata(entry.path());
+            let name = entry.file_name().to_string_lossy();
+            let link = match (&target, self.get("ln")) {
+                (Err(_), _) if self.get("or").is_some() => self.get("or"),
+                (Ok(target), Some("target")) => self.style(&name, target.file_type(), Some(target)),
+                (_, Some("target")) => None,
+                (_, style) => style,
+            };
+            let target_name = fields::link_target(entry).map(|path| path.to_string_lossy());
+            let target = match (&target, target_name) {
+                (Ok(m), Some(target_name)) => self.style(&target_name, m.file_type(), Some(m)),
+                (Ok(_), None) => None,
+                (Err(_), _) => self.get("or"),
+            };
+            [link.map(str::to_string), target.map(str::to_string)]
+        })
+    }
+
+    /// Appends the target of a symbolic link to `out`, colored as a file of its own.
+    pub fn paint_target(&self, out: &mut String, entry: &DirEntry, target: &Path) {
+        let target_str = target.to_string_lossy();
+        match &self.prefetch(entry)[1] {
+            Some(style) => push_styled(out, style, &target_str),
+            None => out.push_str(&target_str),
+        }
+    }
+}
+
+fn push_styled(out: &mut String, style: &str, s: &str) {
+    out.push_str("\x1b[");
+    out.push_str(style);
+    out.push('m');
+    out.push_str(s);
+    out.push_str("\x1b[0m");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paint_all(colors: &Colors, root: &Path) -> Vec<String> {
+        let walker = bfind::WalkBuilder::new(root).prefetch_metadata(true).build().unwrap();
+        let mut lines: Vec<_> = walker.map(|entry| {
+            let entry = entry.unwrap();
+            let shown = Path::new("dir").join(entry.file_name());
+            let mut out = String::new();
+            colors.paint(&mut out, &entry, &shown, entry.metadata().ok());
+            out
+        }).collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn parse() {
+        let colors = Colors::parse("di=01;34:fi=0:*.tar=31:*.TAR=35:bogus:ln=target");
+        assert_eq!(colors.get("di"), Some("01;34"));
+        assert_eq!(colors.get("fi"), None);
+        assert_eq!(colors.get("ln"), Some("target"));
+        assert_eq!(colors.suffixes, [(".tar".to_string(), "35".to_string()), (".tar".to_string(), "31".to_string())]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn paint() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        let path = |name| root.path().join(name);
+        fs::create_dir(path("d")).unwrap();
+        fs::write(path("a.TAR"), "").unwrap();
+        fs::write(path("run"), "").unwrap();
+        fs::set_permissions(path("run"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(path("plain"), "").unwrap();
+        std::os::unix::fs::symlink("missing", path("broken")).unwrap();
+
+        let colors = Colors::parse(DEFAULT_LS_COLORS);
+        assert_eq!(paint_all(&Colors::parse(&format!("{}:*.tar=31", DEFAULT_LS_COLORS)), root.path()), [
+            "\x1b[2mdir/\x1b[0m\x1b[01;32mrun\x1b[0m",
+            "\x1b[2mdir/\x1b[0m\x1b[01;34md\x1b[0m",
+            "\x1b[2mdir/\x1b[0m\x1b[31ma.TAR\x1b[0m",
+            "\x1b[2mdir/\x1b[0m\x1b[40;31;01mbroken\x1b[0m",
+            "\x1b[2mdir/\x1b[0mplain",
+        ]);
+        let mut out = String::new();
+        let entry = bfind::WalkBuilder::new(root.path()).build().unwrap()
+            .map(Result::unwrap)
+            .find(|entry| entry.file_name() == "plain")
+            .unwrap();
+        colors.paint(&mut out, &entry, Path::new("plain"), None);
+        assert_eq!(out, "plain");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn prefetched_link_styles() {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        fs::create_dir(root.path().join("d")).unwrap();
+        std::os::unix::fs::symlink("d", root.path().join("l")).unwrap();
+        let colors = std::sync::Arc::new(Colors::parse(&format!("{}:ln=target", DEFAULT_LS_COLORS)));
+        let walker = bfind::WalkBuilder::new(root.path()).filter({
+            let colors = std::sync::Arc::clone(&colors);
+            move |entry| {
+                colors.prefetch(entry);
+                entry.is_symlink()
+            }
+        });
+        let entry = walker.build().unwrap().next().unwrap().unwrap();
+        // Styled by the filter, so the printer does not look at the target anymore.
+        fs::remove_dir(root.path().join("d")).unwrap();
+        let mut out = String::new();
+        colors.paint(&mut out, &entry, Path::new("l"), None);
+        out.push_str(" -> ");
+        colors.paint_target(&mut out, &entry, Path::new("d"));
+        assert_eq!(out, "\x1b[01;34ml\x1b[0m -> \x1b[01;34md\x1b[0m");
+    }
+}
@@ -0,0 +1,195 @@
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use thiserror::Error;
+use toml::Value;
+
+use crate::cli;
+use crate::cli::Opt;
+use crate::Options;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("std::io::Error {{ kind = {} }}: {source}", source.kind())]
+    Io {
+        #[from]
+        source: io::Error
+    },
+
+    #[error("{source}")]
+    Toml {
+        #[from]
+        source: toml::de::Error
+    },
+
+    #[error("unknown option \"{0}\"")]
+    UnknownOption(String),
+
+    #[error("invalid value for \"{0}\"")]
+    InvalidValue(String),
+
+    #[error("unterminated quote")]
+    UnterminatedQuote,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub const ENV_VAR: &str = "BFIND_OPTS";
+
+pub fn path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("bfind").join("config.toml"))
+}
+
+/// Reads the configuration file and converts it to command line arguments. A missing file is not an error.
+pub fn load(path: &Path) -> Result<Vec<String>> {
+    match fs::read_to_string(path) {
+        Ok(s) => parse(&s),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn parse(s: &str) -> Result<Vec<String>> {
+    let table: toml::Table = s.parse()?;
+    let mut args = Vec::new();
+    for (key, value) in &table {
+        let spec = cli::OPTIONS.iter()
+            .find(|spec| spec.configurable && spec.config_key() == key)
+            .ok_or_else(|| Error::UnknownOption(key.clone()))?;
+        match (spec.arg, value) {
+            (None, Value::Boolean(true)) => args.push(spec.long.to_string()),
+            (None, Value::Boolean(false)) => {},
+            (Some(_), Value::String(s)) => args.extend([spec.long.to_string(), s.clone()]),
+            (Some(_), Value::Integer(i)) => args.extend([spec.long.to_string(), i.to_string()]),
+            (Some(_), Value::Array(items)) => {
+                let items = items.iter()
+                    .map(|item| item.as_str().ok_or_else(|| Error::InvalidValue(key.clone())))
+                    .collect::<Result<Vec<_>>>()?;
+                args.extend([spec.long.to_string(), items.join(",")]);
+            },
+            _ => return Err(Error::InvalidValue(key.clone())),
+        }
+    }
+    Ok(args)
+}
+
+/// Splits the value of `BFIND_OPTS` into arguments. Whitespace separates arguments, single and double quotes group
+/// them and a backslash escapes the next character outside single quotes.
+pub fn split_args(s: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut quote = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => arg.get_or_insert_with(String::new).push(c),
+            (_, '\\') => {
+                if let Some(c) = chars.next() {
+                    arg.get_or_insert_with(String::new).push(c);
+                }
+            },
+            (Some(_), c) => arg.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                arg.get_or_insert_with(String::new);
+            },
+            (None, c) if c.is_whitespace() => args.extend(arg.take()),
+            (None, c) => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(Error::UnterminatedQuote);
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+/// Formats the effective settings in the format of the configuration file.
+pub fn effective(opts: &Options, num_threads: usize) -> String {
+    let mut s = String::new();
+    for spec in cli::OPTIONS.iter().filter(|spec| spec.configurable) {
+        let value = match spec.opt {
+            Opt::Hidden => Value::Boolean(opts.allow_hidden),
+            Opt::FollowLinks => Value::Boolean(opts.follow_links),
+            Opt::Depth => {
+                if opts.max_depth == i32::MAX {
+                    continue;
+                }
+                Value::Integer(opts.max_depth as i64)
+            },
+            Opt::Ignore => Value::Array(opts.ignores.iter().map(|s| Value::String(s.clone())).collect()),
+            Opt::OneFileSystem => Value::Boolean(opts.one_file_system),
+            Opt::StripCwdPrefix => Value::Boolean(opts.strip_cwd_prefix),
+            Opt::Threads => Value::Integer(num_threads as i64),
+            Opt::Memory => match opts.memory {
+                Some(size) => Value::Integer(size as i64),
+                None => continue,
+            },
+            Opt::SpillDir => match &opts.spill_dir {
+                Some(dir) => Value::String(dir.clone()),
+                None => continue,
+            },
+            Opt::MaxSpill => match opts.max_spill {
+                Some(size) => Value::Integer(size as i64),
+                None => continue,
+            },
+            Opt::NoSpill => Value::Boolean(opts.no_spill),
+            Opt::SpillCompression => Value::String(format!("{:?}", opts.spill_compression).to_lowercase()),
+            Opt::Color => Value::String(opts.color.name().to_string()),
+            Opt::Columns => Value::Array(opts.columns.iter().map(|field| Value::String(field.name().to_string())).collect()),
+            Opt::Binary => Value::Boolean(opts.binary),
+            Opt::MaxContentSize => Value::Integer(opts.max_content_size as i64),
+            Opt::Progress => Value::Boolean(opts.progress),
+            Opt::Stats => Value::Boolean(opts.stats),
+            Opt::StatsJson => Value::Boolean(opts.stats_json),
+            Opt::CheckpointInterval => Value::Integer(opts.checkpoint_interval.as_secs() as i64),
+            _ => unreachable!("{} is not configurable", spec.long),
+        };
+        writeln!(s, "{} = {}", spec.config_key(), value).unwrap();
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() -> Result<()> {
+        let args = parse("hidden = true\nfollow = false\nignore = [\".git\", \"target\"]\nthreads = 4\n")?;
+        assert_eq!(args, ["--hidden", "--ignore", ".git,target", "--threads", "4"]);
+        assert!(matches!(parse("help = true"), Err(Error::UnknownOption(_))));
+        assert!(matches!(parse("hidden = 1"), Err(Error::InvalidValue(_))));
+        assert!(matches!(parse("hidden = "), Err(Error::Toml { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn split_env() -> Result<()> {
+        assert_eq!(split_args("  -H  -I 'a b,c'\t-j\"8\" \"\" x\\ y ")?, ["-H", "-I", "a b,c", "-j8", "", "x y"]);
+        assert!(matches!(split_args("-I 'abc"), Err(Error::UnterminatedQuote)));
+        Ok(())
+    }
+
+    #[test]
+    fn effective_settings_roundtrip() -> Result<()> {
+        let mut opts = Options::new();
+        opts.allow_hidden = true;
+        opts.ignores = vec![".git".to_string()];
+        opts.max_depth = 3;
+        let args = parse(&effective(&opts, 5))?.join(" ");
+        for expected in ["--depth 3", "--hidden", "--ignore .git", "--threads 5"] {
+            assert!(args.contains(expected), "{:?} does not contain {:?}", args, expected);
+        }
+        assert!(!args.contains("--follow"));
+        Ok(())
+    }
+}
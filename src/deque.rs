@@ -0,0 +1,397 @@
+//! A bounded work-stealing queue with a single owner.
+//!
+//! The owner pushes to the back and pops from the front, so its own work is done in FIFO order, which keeps the
+//! traversal approximately breadth-first. Other threads steal batches from the front through a [`Stealer`]. The
+//! design follows the local run queue of the Tokio scheduler: the head packs two indices, the `steal` index where
+//! a thief is still copying entries out and the `real` index where the next pop starts, so that the owner can go on
+//! popping while a steal is in progress, and at most one thief steals from a queue at a time.
+
+use std::mem::MaybeUninit;
+
+use crate::sync::Arc;
+use crate::sync::AtomicU32;
+use crate::sync::AtomicU64;
+use crate::sync::Ordering;
+use crate::sync::UnsafeCell;
+
+struct Inner<T> {
+    /// The `steal` index in the high half and the `real` index in the low half. They are equal unless a thief is
+    /// copying out the entries between them.
+    head:   AtomicU64,
+
+    /// Only written by the owner.
+    tail:   AtomicU32,
+
+    mask:   u32,
+    buf:    Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
+
+unsafe impl<T: Send> Send for Inner<T> {}
+unsafe impl<T: Send> Sync for Inner<T> {}
+
+/// The owner's end of a queue.
+pub struct Worker<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// The end of a queue other threads steal from.
+pub struct Stealer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// Creates a queue holding `capacity` entries, which must be a power of two.
+pub fn new<T>(capacity: usize) -> (Worker<T>, Stealer<T>) {
+    assert!(capacity.is_power_of_two() && capacity <= 1 << 31, "invalid capacity {}", capacity);
+    let buf = (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect();
+    let inner = Arc::new(Inner {
+        head: AtomicU64::new(0),
+        tail: AtomicU32::new(0),
+        mask: capacity as u32 - 1,
+        buf,
+    });
+    (Worker { inner: inner.clone() }, Stealer { inner })
+}
+
+fn pack(steal: u32, real: u32) -> u64 {
+    ((steal as u64) << 32) | real as u64
+}
+
+fn unpack(head: u64) -> (u32, u32) {
+    ((head >> 32) as u32, head as u32)
+}
+
+impl<T> Inner<T> {
+    fn capacity(&self) -> u32 {
+        self.mask + 1
+    }
+
+    fn len(&self) -> u32 {
+        let (_, real) = unpack(self.head.load(Ordering::Acquire));
+        self.tail.load(Ordering::Acquire).wrapping_sub(real)
+    }
+
+    /// # Safety
+    ///
+    /// The slot at `pos` must hold a value that nobody else reads.
+    unsafe fn read(&self, pos: u32) -> T {
+        self.buf[(pos & self.mask) as usize].with_mut(|slot| unsafe { (*slot).assume_init_read() })
+    }
+
+    /// # Safety
+    ///
+    /// The slot at `pos` must be empty and nobody else may access it.
+    unsafe fn write(&self, pos: u32, value: T) {
+        self.buf[(pos & self.mask) as usize].with_mut(|slot| unsafe { (*slot).write(value) });
+    }
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        // No steal is in progress once both ends are gone.
+        let (_, real) = unpack(self.head.load(Ordering::Acquire));
+        let tail = self.tail.load(Ordering::Acquire);
+        let mut pos = real;
+        while pos != tail {
+            drop(unsafe { self.read(pos) });
+            pos = pos.wrapping_add(1);
+        }
+    }
+}
+
+impl<T> Worker<T> {
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity() as usize
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len() as usize
+    }
+
+    /// Number of entries that can be pushed. Slots a thief is still copying out of are not free yet.
+    pub fn room(&self) -> usize {
+        let inner = &*self.inner;
+        let (steal, _) = unpack(inner.head.load(Ordering::Acquire));
+        (inner.capacity() - inner.tail.load(Ordering::Relaxed).wrapping_sub(steal)) as usize
+    }
+
+    /// Appends an entry, or gives it back if the queue is full. Entries a thief is still copying out count as
+    /// being in the queue.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let inner = &*self.inner;
+        if self.room() == 0 {
+            return Err(value);
+        }
+        let tail = inner.tail.load(Ordering::Relaxed);
+        // The slot is free: neither the owner nor a thief reads past `tail`, and the check above keeps the owner
+        // from overwriting entries a thief is copying out.
+        unsafe { inner.write(tail, value) };
+        inner.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Removes the entry at the front.
+    pub fn pop(&mut self) -> Option<T> {
+        let inner = &*self.inner;
+        let mut head = inner.head.load(Ordering::Acquire);
+        let pos = loop {
+            let (steal, real) = unpack(head);
+            if real == inner.tail.load(Ordering::Relaxed) {
+                return None;
+            }
+            let next_real = real.wrapping_add(1);
+            // Move the steal index along with the real one unless a thief owns it.
+            let next = if steal == real {
+                pack(next_real, next_real)
+            } else {
+                pack(steal, next_real)
+            };
+            match inner.head.compare_exchange(head, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => break real,
+                Err(actual) => head = actual,
+            }
+        };
+        // Claimed by the successful compare-exchange.
+        Some(unsafe { inner.read(pos) })
+    }
+}
+
+impl<T> Stealer<T> {
+    /// Moves half of the entries, rounded up, from the front of this queue to the back of `dest`, and returns the
+    /// first of them instead of pushing it. Returns `None` if there was nothing to steal, another thief is stealing
+    /// from this queue, or `dest` is more than half full.
+    pub fn steal_into(&self, dest: &mut Worker<T>) -> Option<T> {
+        let room = dest.room() as u32;
+        let dest_inner = &*dest.inner;
+        if room < dest_inner.capacity() / 2 {
+            return None;
+        }
+        let dest_tail = dest_inner.tail.load(Ordering::Relaxed);
+        // One more than fits, as the first entry is returned.
+        let max = room + 1;
+
+        let (first, n) = self.steal_batch(dest_inner, dest_tail, max)?;
+        if n > 0 {
+            dest_inner.tail.store(dest_tail.wrapping_add(n), Ordering::Release);
+        }
+        Some(first)
+    }
+
+    /// Claims half of the entries, but at most `max`, returns the first one and copies the rest to `dest` starting at `dest_tail`,
+    /// returning how many.
+    fn steal_batch(&self, dest: &Inner<T>, dest_tail: u32, max: u32) -> Option<(T, u32)> {
+        let inner = &*self.inner;
+        let mut head = inner.head.load(Ordering::Acquire);
+        let (n, claimed) = loop {
+            let (steal, real) = unpack(head);
+            if steal != real {
+                return None;
+            }
+            let len = inner.tail.load(Ordering::Acquire).wrapping_sub(real);
+            let n = (len - len / 2).min(max);
+            if n == 0 {
+                return None;
+            }
+            // Take the entries from the owner by moving the real index, but keep them from being overwritten by
+            // leaving the steal index behind until they are copied.
+            let next = pack(steal, real.wrapping_add(n));
+            match inner.head.compare_exchange(head, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => break (n, next),
+                Err(actual) => head = actual,
+            }
+        };
+
+        // The claimed slots are only read here, and the owner does not write to them before the steal index moves
+        // on. The slots after `dest_tail` are free and nobody else reads them until the tail moves past them.
+        let (pos, _) = unpack(claimed);
+        let first = unsafe { inner.read(pos) };
+        for i in 1..n {
+            unsafe {
+                let value = inner.read(pos.wrapping_add(i));
+                dest.write(dest_tail.wrapping_add(i - 1), value);
+            }
+        }
+
+        // Release the slots. The owner may have popped in the meantime, moving the real index.
+        let mut head = claimed;
+        loop {
+            let (_, real) = unpack(head);
+            match inner.head.compare_exchange(head, pack(real, real), Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return Some((first, n - 1)),
+                Err(actual) => head = actual,
+            }
+        }
+    }
+}
+
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    #[test]
+    fn fifo() {
+        let (mut worker, _) = new(4);
+        for i in 0..4 {
+            assert!(worker.push(i).is_ok());
+        }
+        assert_eq!(worker.push(4), Err(4));
+        assert_eq!(worker.pop(), Some(0));
+        assert!(worker.push(4).is_ok());
+        assert_eq!((1..5).map(|_| worker.pop().unwrap()).collect::<Vec<_>>(), [1, 2, 3, 4]);
+        assert_eq!(worker.pop(), None);
+    }
+
+    #[test]
+    fn steal_half_keeps_order() {
+        let (mut victim, stealer) = new(16);
+        let (mut thief, _) = new(16);
+        for i in 0..10 {
+            victim.push(i).unwrap();
+        }
+        assert_eq!(stealer.steal_into(&mut thief), Some(0));
+        assert_eq!(thief.len(), 4);
+        assert_eq!((0..4).map(|_| thief.pop().unwrap()).collect::<Vec<_>>(), [1, 2, 3, 4]);
+        assert_eq!(victim.pop(), Some(5));
+        assert_eq!(victim.len(), 4);
+        assert_eq!(stealer.steal_into(&mut thief), Some(6));
+        assert_eq!(thief.pop(), Some(7));
+        assert_eq!(thief.pop(), None);
+    }
+
+    #[test]
+    fn drops_remaining() {
+        let value = std::sync::Arc::new(());
+        let (mut worker, stealer) = new(4);
+        worker.push(value.clone()).unwrap();
+        worker.push(value.clone()).unwrap();
+        drop(worker);
+        drop(stealer);
+        assert_eq!(std::sync::Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn concurrent_steal() {
+        const N: usize = if cfg!(miri) { 1000 } else { 100_000 };
+        let (mut worker, stealer) = new(256);
+        let done = AtomicBool::new(false);
+        let taken: Vec<usize> = thread::scope(|s| {
+            let thieves: Vec<_> = (0..3).map(|_| {
+                let stealer = stealer.clone();
+                let done = &done;
+                s.spawn(move || {
+                    let (mut local, _) = new(256);
+                    let mut taken = Vec::new();
+                    while !done.load(std::sync::atomic::Ordering::Acquire) || stealer.inner.len() > 0 {
+                        taken.extend(stealer.steal_into(&mut local));
+                        taken.extend(std::iter::from_fn(|| local.pop()));
+                    }
+                    taken
+                })
+            }).collect();
+            let mut taken = Vec::new();
+            let mut next = 0;
+            while next < N {
+                match worker.push(next) {
+                    Ok(()) => next += 1,
+                    Err(_) => taken.extend(worker.pop()),
+                }
+            }
+            taken.extend(std::iter::from_fn(|| worker.pop()));
+            done.store(true, std::sync::atomic::Ordering::Release);
+            for thief in thieves {
+                taken.extend(thief.join().unwrap());
+            }
+            taken
+        });
+        assert_eq!(taken.len(), N);
+        assert_eq!(taken.into_iter().collect::<HashSet<_>>().len(), N);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    //! Run with `LOOM_MAX_PREEMPTIONS=3 RUSTFLAGS="--cfg loom" cargo test --release --lib loom_tests`.
+
+    use super::*;
+    use loom::thread;
+
+    #[test]
+    fn pop_and_steal() {
+        loom::model(|| {
+            let (mut worker, stealer) = new(4);
+            for i in 0..3 {
+                worker.push(i).unwrap();
+            }
+            let thief = thread::spawn(move || {
+                let (mut local, _) = new(4);
+                let mut taken: Vec<i32> = stealer.steal_into(&mut local).into_iter().collect();
+                taken.extend(std::iter::from_fn(|| local.pop()));
+                taken
+            });
+            let mut taken: Vec<i32> = std::iter::from_fn(|| worker.pop()).collect();
+            let stolen = thief.join().unwrap();
+            // Stolen entries come from the front, in order.
+            assert!(stolen.windows(2).all(|w| w[0] < w[1]));
+            taken.extend(stolen);
+            taken.sort();
+            assert_eq!(taken, [0, 1, 2]);
+        });
+    }
+
+    #[test]
+    fn push_during_steal() {
+        loom::model(|| {
+            let (mut worker, stealer) = new(2);
+            worker.push(0).unwrap();
+            worker.push(1).unwrap();
+            let thief = thread::spawn(move || {
+                let (mut local, _) = new(2);
+                let mut taken: Vec<i32> = stealer.steal_into(&mut local).into_iter().collect();
+                taken.extend(std::iter::from_fn(|| local.pop()));
+                taken
+            });
+            // Pushing into the slot freed by the pop must not overwrite the entry the thief may be copying out.
+            let mut taken: Vec<i32> = worker.pop().into_iter().collect();
+            if let Err(value) = worker.push(2) {
+                taken.push(value);
+            }
+            taken.extend(std::iter::from_fn(|| worker.pop()));
+            taken.extend(thief.join().unwrap());
+            taken.sort();
+            assert_eq!(taken, [0, 1, 2]);
+        });
+    }
+
+    #[test]
+    fn two_thieves() {
+        loom::model(|| {
+            let (mut worker, stealer) = new(4);
+            for i in 0..4 {
+                worker.push(i).unwrap();
+            }
+            let thieves: Vec<_> = (0..2).map(|_| {
+                let stealer = stealer.clone();
+                thread::spawn(move || {
+                    let (mut local, _) = new(4);
+                    let mut taken: Vec<i32> = stealer.steal_into(&mut local).into_iter().collect();
+                    taken.extend(std::iter::from_fn(|| local.pop()));
+                    taken
+                })
+            }).collect();
+            let mut taken: Vec<i32> = std::iter::from_fn(|| worker.pop()).collect();
+            for thief in thieves {
+                taken.extend(thief.join().unwrap());
+            }
+            taken.sort();
+            assert_eq!(taken, [0, 1, 2, 3]);
+        });
+    }
+}
@@ -0,0 +1,208 @@
+//! The `du` verb: the sizes of the entries are added up per directory, then the largest directories at each depth
+//! are printed once the walk is done.
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use bfind::DirEntry;
+
+use crate::long::human_size;
+
+/// Number of directories printed at each depth if `du` is not given a number.
+pub const DEFAULT_TOP: usize = 10;
+
+/// Sizes of the entries in a directory and below it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Size {
+    /// Sum of the file lengths.
+    apparent:   u64,
+
+    /// Space allocated on disk.
+    disk:       u64,
+}
+
+impl Size {
+    fn of(metadata: &fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let disk = metadata.blocks() * 512;
+        #[cfg(not(unix))]
+        let disk = metadata.len();
+        Self { apparent: metadata.len(), disk }
+    }
+
+    fn add(&mut self, other: Size) {
+        self.apparent += other.apparent;
+        self.disk += other.disk;
+    }
+}
+
+struct Dir {
+    depth:      usize,
+    size:       Size,
+}
+
+pub struct Du {
+    dirs:       HashMap<PathBuf, Dir>,
+
+    /// Device and inode numbers of the files with several hard links that were counted already.
+    links:      HashSet<(u64, u64)>,
+
+    /// Number of directories printed at each depth.
+    top:        usize,
+}
+
+impl Du {
+    pub fn new<I: IntoIterator<Item = PathBuf>>(roots: I, top: usize) -> Self {
+        let dirs = roots.into_iter()
+            .map(|root| {
+                let size = fs::metadata(&root).map_or(Size::default(), |m| Size::of(&m));
+                (root, Dir { depth: 0, size })
+            })
+            .collect();
+        Self { dirs, links: HashSet::new(), top }
+    }
+
+    /// Adds the size of an entry to its directory. The entries of a directory may be yielded before the directory
+    /// itself, or without it if an expression skips it, so either creates the directory and those above it.
+    pub fn insert(&mut self, entry: &DirEntry, metadata: Option<&fs::Metadata>) {
+        let size = match metadata {
+            #[cfg(unix)]
+            Some(metadata) if !metadata.is_dir() && metadata.nlink() > 1 => {
+                if !self.links.insert((metadata.dev(), metadata.ino())) {
+                    return;
+                }
+                Size::of(metadata)
+            },
+            Some(metadata) => Size::of(metadata),
+            None => Size::default(),
+        };
+        let (path, depth) = if entry.file_type().is_dir() {
+            (entry.path(), entry.depth())
+        } else {
+            match entry.path().parent() {
+                Some(parent) => (parent, entry.depth() - 1),
+                None => return,
+            }
+        };
+        match self.dirs.get_mut(path) {
+            Some(dir) => dir.size.add(size),
+            None => {
+                self.dirs.insert(path.to_path_buf(), Dir { depth, size });
+            },
+        }
+        // Directories are created along with their ancestors, so the first one found ends the climb.
+        let mut ancestor = path;
+        for depth in (0..depth).rev() {
+            match ancestor.parent() {
+                Some(parent) if !self.dirs.contains_key(parent) => {
+                    self.dirs.insert(parent.to_path_buf(), Dir { depth, size: Size::default() });
+                    ancestor = parent;
+                },
+                _ => break,
+            }
+        }
+    }
+
+    /// Adds the total of each directory to its parent, deepest first, then prints the largest directories by size
+    /// on disk, grouped by depth.
+    pub fn render(&mut self) -> String {
+        let mut paths: Vec<_> = self.dirs.iter().map(|(path, dir)| (dir.depth, path.clone())).collect();
+        paths.sort_unstable_by_key(|(depth, _)| std::cmp::Reverse(*depth));
+        for (depth, path) in &paths {
+            if *depth == 0 {
+                continue;
+            }
+            let size = self.dirs[path].size;
+            if let Some(parent) = path.parent().and_then(|parent| self.dirs.get_mut(parent)) {
+                parent.size.add(size);
+            }
+        }
+
+        let mut by_depth: BTreeMap<usize, Vec<(&Path, Size)>> = BTreeMap::new();
+        for (path, dir) in &self.dirs {
+            by_depth.entry(dir.depth).or_default().push((path, dir.size));
+        }
+        let mut out = String::new();
+        for (depth, mut dirs) in by_depth {
+            dirs.sort_unstable_by(|a, b| b.1.disk.cmp(&a.1.disk).then(b.1.apparent.cmp(&a.1.apparent)).then(a.0.cmp(b.0)));
+            writeln!(out, "depth {}", depth).unwrap();
+            for (path, size) in dirs.into_iter().take(self.top) {
+                writeln!(out, "{:>6} {:>6}  {}", human_size(size.disk), human_size(size.apparent), path.display()).unwrap();
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totals() {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        let path = |name: &str| root.path().join(name);
+        fs::create_dir_all(path("a/b")).unwrap();
+        fs::create_dir(path("c")).unwrap();
+        fs::write(path("a/f"), vec![0; 3000]).unwrap();
+        fs::write(path("a/b/g"), vec![0; 5000]).unwrap();
+        fs::write(path("c/h"), vec![0; 100]).unwrap();
+        #[cfg(unix)]
+        fs::hard_link(path("a/b/g"), path("a/b/g2")).unwrap();
+
+        let walker = bfind::WalkBuilder::new(root.path()).prefetch_metadata(true).build().unwrap();
+        let mut du = Du::new([root.path().to_path_buf()], 1);
+        for entry in walker {
+            let entry = entry.unwrap();
+            du.insert(&entry, entry.metadata().ok());
+        }
+        let out = du.render().replace(&root.path().display().to_string(), "root");
+        let dir_size = |name: &str| fs::metadata(path(name)).unwrap().len();
+        let apparent = |name: &str| du.dirs[&path(name)].size.apparent;
+        assert_eq!(apparent("a/b"), dir_size("a/b") + 5000);
+        assert_eq!(apparent("a"), dir_size("a") + dir_size("a/b") + 8000);
+        assert_eq!(apparent(""), dir_size("") + dir_size("a") + dir_size("a/b") + dir_size("c") + 8100);
+
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 6, "{}", out);
+        assert_eq!(lines[0], "depth 0");
+        assert!(lines[2] == "depth 1" && lines[3].ends_with("  root/a"), "{}", out);
+        assert!(lines[4] == "depth 2" && lines[5].ends_with("  root/a/b"), "{}", out);
+    }
+
+    #[test]
+    fn totals_with_expression() {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        let path = |name: &str| root.path().join(name);
+        fs::create_dir_all(path("a/b")).unwrap();
+        fs::write(path("a/b/x.log"), vec![0; 5000]).unwrap();
+        fs::write(path("a/y.txt"), vec![0; 3000]).unwrap();
+
+        let tokens: Vec<String> = ["name", "glob", "*.log"].iter().map(|s| s.to_string()).collect();
+        let content = crate::expr::ContentOptions { binary: false, max_size: 1000 };
+        let matcher = crate::expr::Matcher::parse(&tokens, content).unwrap();
+        let walker = bfind::WalkBuilder::new(root.path())
+            .prefetch_metadata(true)
+            .filter(move |entry| matcher.matches(entry))
+            .build()
+            .unwrap();
+        let mut du = Du::new([root.path().to_path_buf()], 10);
+        for entry in walker {
+            let entry = entry.unwrap();
+            du.insert(&entry, entry.metadata().ok());
+        }
+        du.render();
+        let dir = |name: &str| du.dirs.get(&path(name)).map(|dir| (dir.depth, dir.size.apparent));
+        let root_size = fs::metadata(root.path()).unwrap().len();
+        assert_eq!(dir("a/b"), Some((2, 5000)));
+        assert_eq!(dir("a"), Some((1, 5000)));
+        assert_eq!(dir(""), Some((0, root_size + 5000)));
+    }
+}
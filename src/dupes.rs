@@ -0,0 +1,232 @@
+//! The `dupes` verb: files with the same content are found by grouping them by size, then by a hash of their first
+//! block, then by a hash of their whole content, so that most files are never read in full.
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::hash::Hash;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
+
+use bfind::DirEntry;
+
+use crate::json;
+use crate::json::Value;
+use crate::long::human_size;
+
+/// Number of bytes hashed to tell files of the same size apart before hashing them in full.
+const PARTIAL: u64 = 4096;
+
+/// Files with the same size and content.
+#[derive(Debug, PartialEq)]
+struct Group {
+    size:       u64,
+    hash:       blake3::Hash,
+    paths:      Vec<PathBuf>,
+}
+
+pub struct Dupes {
+    by_size:    HashMap<u64, Vec<PathBuf>>,
+
+    /// Device and inode numbers of the files seen, so that hard links to a file seen already are skipped.
+    inodes:     HashSet<(u64, u64)>,
+
+    /// Number of threads reading files.
+    threads:    usize,
+    json:       bool,
+}
+
+impl Dupes {
+    pub fn new(threads: usize, json: bool) -> Self {
+        Self { by_size: HashMap::new(), inodes: HashSet::new(), threads, json }
+    }
+
+    /// Adds a regular file. Empty files and other types of entries are skipped.
+    pub fn insert(&mut self, entry: &DirEntry, metadata: Option<&fs::Metadata>) {
+        let Some(metadata) = metadata.filter(|m| m.is_file() && m.len() > 0) else {
+            return;
+        };
+        #[cfg(unix)]
+        if !self.inodes.insert((metadata.dev(), metadata.ino())) {
+            return;
+        }
+        self.by_size.entry(metadata.len()).or_default().push(entry.path().to_path_buf());
+    }
+
+    /// Hashes the files that have the same size as another one, and returns the groups of identical files, largest
+    /// first. Files that cannot be read are left out and their errors are added to `errors`.
+    ///
+    /// This runs after the walk rather than in its workers, since which sizes are unique is only known at the end,
+    /// and hashing during the walk would read the first block of every file.
+    fn groups(&mut self, errors: &mut Vec<bfind::Error>) -> Vec<Group> {
+        let candidates = std::mem::take(&mut self.by_size).into_iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .flat_map(|(size, paths)| paths.into_iter().map(move |path| (size, path)))
+            .collect();
+        let partial = hash_files(candidates, Some(PARTIAL), self.threads, errors);
+
+        // The first block of small files is all of them.
+        let mut full = Vec::new();
+        let mut groups = Vec::new();
+        for ((size, hash), paths) in group_by(partial) {
+            if size <= PARTIAL {
+                groups.push(Group { size, hash, paths });
+            } else {
+                full.extend(paths.into_iter().map(|path| (size, path)));
+            }
+        }
+        let full = hash_files(full, None, self.threads, errors);
+        groups.extend(group_by(full).into_iter().map(|((size, hash), paths)| Group { size, hash, paths }));
+
+        for group in &mut groups {
+            group.paths.sort();
+        }
+        groups.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.paths.cmp(&b.paths)));
+        groups
+    }
+
+    /// Prints the groups of identical files, as one JSON object per line with `--json`, or else as a header line
+    /// followed by the paths, with a blank line between groups.
+    pub fn render(&mut self, errors: &mut Vec<bfind::Error>) -> String {
+        let mut out = String::new();
+        for (i, group) in self.groups(errors).into_iter().enumerate() {
+            if self.json {
+                let record = Value::object([
+                    ("size", Value::UInt(group.size)),
+                    ("hash", Value::from(group.hash.to_hex().as_str())),
+                    ("paths", Value::Array(group.paths.iter().map(|path| json::os_str(path.as_os_str())).collect())),
+                ]);
+                writeln!(out, "{}", record).unwrap();
+                continue;
+            }
+            if i > 0 {
+                out.push('\n');
+            }
+            writeln!(out, "{} files of {} bytes ({}):", group.paths.len(), group.size, human_size(group.size)).unwrap();
+            for path in &group.paths {
+                writeln!(out, "{}", path.display()).unwrap();
+            }
+        }
+        out
+    }
+}
+
+/// Groups the keys of the values that are equal, leaving out the values that are unique.
+fn group_by<K: Hash + Eq, T>(items: Vec<(K, T)>) -> Vec<(K, Vec<T>)> {
+    let mut groups: HashMap<K, Vec<T>> = HashMap::new();
+    for (key, item) in items {
+        groups.entry(key).or_default().push(item);
+    }
+    groups.into_iter().filter(|(_, items)| items.len() > 1).collect()
+}
+
+fn hash_file(path: &Path, limit: Option<u64>) -> io::Result<blake3::Hash> {
+    let file = fs::File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    match limit {
+        Some(limit) => io::copy(&mut io::Read::take(file, limit), &mut hasher)?,
+        None => io::copy(&mut io::BufReader::with_capacity(1 << 16, file), &mut hasher)?,
+    };
+    Ok(hasher.finalize())
+}
+
+/// Hashes the first `limit` bytes, or all, of each file with `threads` threads, and returns the files keyed by
+/// size and hash.
+fn hash_files(files: Vec<(u64, PathBuf)>, limit: Option<u64>, threads: usize,
+              errors: &mut Vec<bfind::Error>) -> Vec<((u64, blake3::Hash), PathBuf)> {
+    let next = AtomicUsize::new(0);
+    let results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads.clamp(1, files.len().max(1))).map(|_| scope.spawn(|| {
+            let mut results = Vec::new();
+            loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some((_, path)) = files.get(i) else {
+                    return results;
+                };
+                results.push((i, hash_file(path, limit)));
+            }
+        })).collect();
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    });
+
+    let mut hashes = Vec::with_capacity(results.len());
+    let mut files: Vec<_> = files.into_iter().map(Some).collect();
+    for (i, result) in results {
+        let (size, path) = files[i].take().unwrap();
+        match result {
+            Ok(hash) => hashes.push(((size, hash), path)),
+            Err(e) => errors.push(bfind::Error::Io { path, source: e }),
+        }
+    }
+    hashes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_dupes() {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        let path = |name: &str| root.path().join(name);
+        fs::create_dir(path("d")).unwrap();
+        let big: Vec<u8> = (0..10000u32).map(|i| i as u8).collect();
+        let mut big_changed = big.clone();
+        big_changed[9000] ^= 1;
+        for (name, content) in [("a", &b"hello"[..]), ("d/b", b"hello"), ("c", b"world"), ("e", b""), ("f", b""),
+                                ("big1", &big), ("d/big2", &big), ("big3", &big_changed)] {
+            fs::write(path(name), content).unwrap();
+        }
+        #[cfg(unix)]
+        fs::hard_link(path("a"), path("d/a")).unwrap();
+
+        let walker = bfind::WalkBuilder::new(root.path()).prefetch_metadata(true).build().unwrap();
+        let mut dupes = Dupes::new(3, false);
+        for entry in walker {
+            let entry = entry.unwrap();
+            dupes.insert(&entry, entry.metadata().ok());
+        }
+        let mut errors = Vec::new();
+        let groups = dupes.groups(&mut errors);
+        assert!(errors.is_empty());
+        assert_eq!(groups.len(), 2, "{:?}", groups);
+        assert_eq!((groups[0].size, &groups[0].paths), (10000, &vec![path("big1"), path("d/big2")]));
+        assert_eq!(groups[0].hash, blake3::hash(&big));
+        assert_eq!(groups[1].size, 5);
+        assert_eq!(groups[1].paths.len(), 2);
+        assert!(groups[1].paths.contains(&path("d/b")), "{:?}", groups[1]);
+    }
+
+    #[test]
+    fn full_hash_splits() {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        let path = |name: &str| root.path().join(name);
+        let big: Vec<u8> = (0..10000u32).map(|i| i as u8).collect();
+        let mut big_changed = big.clone();
+        big_changed[PARTIAL as usize + 100] ^= 1;
+        fs::write(path("big1"), &big).unwrap();
+        fs::write(path("big3"), &big_changed).unwrap();
+
+        let walker = bfind::WalkBuilder::new(root.path()).prefetch_metadata(true).build().unwrap();
+        let mut dupes = Dupes::new(2, false);
+        for entry in walker {
+            let entry = entry.unwrap();
+            dupes.insert(&entry, entry.metadata().ok());
+        }
+        // Same size and first block, so only the full hash tells them apart.
+        let mut errors = Vec::new();
+        let files = vec![(10000, path("big1")), (10000, path("big3"))];
+        let partial = hash_files(files, Some(PARTIAL), 2, &mut errors);
+        assert_eq!(group_by(partial).len(), 1);
+        let groups = dupes.groups(&mut errors);
+        assert!(errors.is_empty());
+        assert!(!groups.iter().any(|group| group.paths.contains(&path("big3"))), "{:?}", groups);
+    }
+}
@@ -0,0 +1,93 @@
+//! The `exec` verb: a command is run for each matching entry, with placeholders filled in its arguments, and waited
+//! for before the next entry.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+use bfind::DirEntry;
+
+use crate::fields::Field;
+use crate::hash::Algorithm;
+use crate::hash::Digests;
+use crate::template::Template;
+
+pub struct Exec {
+    /// The command and its arguments.
+    args:       Vec<Template>,
+}
+
+impl Exec {
+    /// `args` holds the command and its arguments, so it must not be empty.
+    pub fn new(args: Vec<Template>) -> Self {
+        assert!(!args.is_empty(), "exec takes a COMMAND");
+        Self { args }
+    }
+
+    pub fn needs_metadata(&self) -> bool {
+        self.args.iter().any(Template::needs_metadata)
+    }
+
+    pub fn uses(&self, field: Field) -> bool {
+        self.args.iter().any(|arg| arg.uses(field))
+    }
+
+    pub fn algorithms(&self) -> Vec<Algorithm> {
+        let mut algorithms = Vec::new();
+        for algorithm in self.args.iter().flat_map(Template::algorithms) {
+            if !algorithms.contains(&algorithm) {
+                algorithms.push(algorithm);
+            }
+        }
+        algorithms
+    }
+
+    /// The command line for an entry.
+    pub fn args(&self, entry: &DirEntry, shown: &Path, metadata: Option<&fs::Metadata>,
+                digests: &Digests) -> Vec<OsString> {
+        self.args.iter().map(|arg| arg.render_os(entry, shown, metadata, digests)).collect()
+    }
+}
+
+/// Runs a command line and waits for it, returning whether it exited successfully.
+pub fn run(args: &[OsString]) -> io::Result<bool> {
+    Ok(Command::new(&args[0]).args(&args[1..]).status()?.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates(args: &[&str]) -> Vec<Template> {
+        args.iter().map(|arg| Template::parse(arg).unwrap()).collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_commands() {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        fs::write(root.path().join("f"), "hello").unwrap();
+        let entry = bfind::WalkBuilder::new(root.path()).build().unwrap().next().unwrap().unwrap();
+        let shown = Path::new("f");
+
+        let exec = Exec::new(templates(&["test", "-f", "{fullpath}", "-a", "{size}", "-eq", "5"]));
+        assert!(exec.needs_metadata() && exec.algorithms().is_empty());
+        let args = exec.args(&entry, shown, entry.metadata().ok(), &Digests::default());
+        assert_eq!(args[2], entry.path().as_os_str());
+        assert!(run(&args).unwrap());
+
+        let exec = Exec::new(templates(&["test", "-d", "{fullpath}"]));
+        assert!(!run(&exec.args(&entry, shown, None, &Digests::default())).unwrap());
+
+        let missing = Exec::new(templates(&["bfind-no-such-command", "{name}"]));
+        assert!(run(&missing.args(&entry, shown, None, &Digests::default())).is_err());
+    }
+
+    #[test]
+    fn algorithms_once() {
+        let exec = Exec::new(templates(&["echo", "{sha256}", "{md5}", "{sha256}"]));
+        assert_eq!(exec.algorithms().len(), 2);
+    }
+}
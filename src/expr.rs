@@ -0,0 +1,615 @@
+//! The expression after `--` that selects the entries to act on, like `name glob '*.rs' and not type is dir`.
+//!
+//! `not` binds tighter than `and`, which binds tighter than `or`, and predicates next to each other are joined by
+//! `and`. The expression is evaluated in the worker threads, so reading the contents of files for `content`
+//! predicates happens in parallel and shallow files are still found first.
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::cell::OnceCell;
+use std::fs;
+use std::io::Read;
+use std::iter::Peekable;
+use std::slice;
+use std::sync::Arc;
+use std::sync::Mutex;
+use thiserror::Error;
+
+use bfind::DirEntry;
+
+use crate::cli;
+use crate::fields;
+use crate::hash::Algorithm;
+use crate::hash::Hashes;
+use crate::users;
+use crate::users::Users;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("expected {0} at the end of the expression")]
+    UnexpectedEnd(&'static str),
+
+    #[error("expected {expected} in the expression, got \"{got}\"")]
+    Unexpected { expected: &'static str, got: String },
+
+    #[error("invalid regular expression \"{pattern}\": {source}")]
+    Regex { pattern: String, source: regex::Error },
+
+    #[error("unknown file type \"{0}\"")]
+    UnknownType(String),
+
+    #[error("unable to parse \"{0}\" as a size")]
+    InvalidSize(String),
+
+    #[error("invalid hash \"{0}\", expected [sha256:|blake3:|md5:]HEX")]
+    InvalidHash(String),
+
+    #[error("invalid mode \"{0}\", expected octal bits like 644 or symbolic ones like u+x,g+w")]
+    InvalidMode(String),
+
+    #[error("unknown user \"{0}\"")]
+    UnknownUser(String),
+
+    #[error("unknown group \"{0}\"")]
+    UnknownGroup(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The names `type is` accepts, as printed by `{type}`.
+const TYPES: &[&str] = &["file", "dir", "link", "fifo", "socket", "block", "char"];
+
+/// Number of bytes at the start of a file that are checked for a NUL byte to tell whether it is binary, as grep does.
+const BINARY_CHECK: usize = 8192;
+
+#[derive(Debug)]
+enum Predicate {
+    /// `name match REGEX` and `name glob PATTERN`, which is translated to an anchored regular expression.
+    Name(regex::Regex),
+    Type(&'static str),
+    SizeGt(u64),
+
+    /// `content match REGEX` and `content contains STRING`, which is an escaped regular expression.
+    Content(regex::bytes::Regex),
+
+    /// `hash eq [ALGORITHM:]HEX`, with the hash in lower case.
+    Hash(Algorithm, String),
+
+    /// `perm has MODE`, `perm eq MODE` and `perm any MODE`, on the permission bits including setuid, setgid and
+    /// sticky.
+    Perm(PermTest, u32),
+    User(u32),
+    Group(u32),
+    NoUser,
+    NoGroup,
+
+    /// `readable`, `writable` and `executable`, as checked by access(2) for the real user.
+    Access(Access),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PermTest {
+    /// All the bits are set.
+    Has,
+
+    /// The bits are exactly these.
+    Eq,
+
+    /// Any of the bits is set.
+    Any,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Predicate(Predicate),
+}
+
+/// How the contents of files are read for `content` predicates.
+#[derive(Clone, Copy, Debug)]
+pub struct ContentOptions {
+    /// Also search files with a NUL byte near the start.
+    pub binary:     bool,
+
+    /// Files larger than this never match.
+    pub max_size:   u64,
+}
+
+/// A parsed expression, ready to be evaluated on entries.
+#[derive(Debug)]
+pub struct Matcher {
+    expr:       Expr,
+    content:    ContentOptions,
+
+    /// For `nouser` and `nogroup`, shared by the worker threads.
+    users:      Arc<Mutex<Users>>,
+
+    /// For `hash eq`, whose digests are attached to the entries.
+    hashes:     Arc<Hashes>,
+}
+
+fn regex(pattern: &str) -> Result<regex::Regex> {
+    regex::Regex::new(pattern).map_err(|source| Error::Regex { pattern: pattern.to_string(), source })
+}
+
+fn bytes_regex(pattern: &str) -> Result<regex::bytes::Regex> {
+    regex::bytes::Regex::new(pattern).map_err(|source| Error::Regex { pattern: pattern.to_string(), source })
+}
+
+/// Translates a shell glob, with `*`, `?` and `[...]` classes, to a regular expression matching whole names.
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^(?s:");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            '[' if has_class_end(chars.clone()) => {
+                re.push('[');
+                if chars.next_if(|&c| c == '!' || c == '^').is_some() {
+                    re.push('^');
+                }
+                // A `]` right after the opening bracket is part of the class.
+                if chars.next_if_eq(&']').is_some() {
+                    re.push_str("\\]");
+                }
+                for c in chars.by_ref() {
+                    match c {
+                        ']' => break,
+                        '\\' | '[' | '&' | '~' => {
+                            re.push('\\');
+                            re.push(c);
+                        },
+                        c => re.push(c),
+                    }
+                }
+                re.push(']');
+            },
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    re.push_str(")$");
+    re
+}
+
+/// Whether the rest of a glob after a `[` closes the class. Otherwise the `[` is literal.
+fn has_class_end(mut rest: Peekable<std::str::Chars>) -> bool {
+    rest.next_if(|&c| c == '!' || c == '^');
+    rest.next_if_eq(&']');
+    rest.any(|c| c == ']')
+}
+
+struct Parser<'a> {
+    tokens:     Peekable<slice::Iter<'a, String>>,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self, expected: &'static str) -> Result<&'a str> {
+        self.tokens.next().map(String::as_str).ok_or(Error::UnexpectedEnd(expected))
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.tokens.next_if(|t| *t == token).is_some()
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.eat("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.not()?;
+        loop {
+            if !self.eat("and") && matches!(self.tokens.peek().map(|t| t.as_str()), None | Some("or" | ")")) {
+                return Ok(expr);
+            }
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.eat("not") {
+            Ok(Expr::Not(Box::new(self.not()?)))
+        } else if self.eat("(") {
+            let expr = self.or()?;
+            match self.next("\")\"")? {
+                ")" => Ok(expr),
+                got => Err(Error::Unexpected { expected: "\")\"", got: got.to_string() }),
+            }
+        } else {
+            self.predicate().map(Expr::Predicate)
+        }
+    }
+
+    fn predicate(&mut self) -> Result<Predicate> {
+        const EXPECTED: &str = "a predicate, \"not\" or \"(\"";
+        let subject = self.next(EXPECTED)?;
+        match subject {
+            "nouser" => return Ok(Predicate::NoUser),
+            "nogroup" => return Ok(Predicate::NoGroup),
+            "readable" => return Ok(Predicate::Access(Access::Read)),
+            "writable" => return Ok(Predicate::Access(Access::Write)),
+            "executable" => return Ok(Predicate::Access(Access::Execute)),
+            _ => {},
+        }
+        let (verbs, expected): (&[&str], _) = match subject {
+            "name" => (&["match", "glob"], "\"match\" or \"glob\""),
+            "type" => (&["is"], "\"is\""),
+            "size" => (&["gt"], "\"gt\""),
+            "content" => (&["match", "contains"], "\"match\" or \"contains\""),
+            "hash" => (&["eq"], "\"eq\""),
+            "perm" => (&["has", "eq", "any"], "\"has\", \"eq\" or \"any\""),
+            "user" | "group" => (&["is"], "\"is\""),
+            got => return Err(Error::Unexpected { expected: EXPECTED, got: got.to_string() }),
+        };
+        let verb = self.next(expected)?;
+        if !verbs.contains(&verb) {
+            return Err(Error::Unexpected { expected, got: verb.to_string() });
+        }
+        let arg = self.next("an argument")?;
+        Ok(match (subject, verb) {
+            ("name", "match") => Predicate::Name(regex(arg)?),
+            ("name", "glob") => Predicate::Name(regex(&glob_to_regex(arg))?),
+            ("type", _) => match TYPES.iter().find(|&&name| name == arg) {
+                Some(name) => Predicate::Type(name),
+                None => return Err(Error::UnknownType(arg.to_string())),
+            },
+            ("size", _) => Predicate::SizeGt(cli::parse_size(arg).ok_or_else(|| Error::InvalidSize(arg.to_string()))?),
+            ("content", "match") => Predicate::Content(bytes_regex(arg)?),
+            ("content", _) => Predicate::Content(bytes_regex(&regex::escape(arg))?),
+            ("hash", _) => parse_hash(arg).ok_or_else(|| Error::InvalidHash(arg.to_string()))?,
+            ("perm", verb) => {
+                let test = match verb {
+                    "has" => PermTest::Has,
+                    "eq" => PermTest::Eq,
+                    _ => PermTest::Any,
+                };
+                Predicate::Perm(test, parse_mode(arg).ok_or_else(|| Error::InvalidMode(arg.to_string()))?)
+            },
+            ("user", _) => match arg.parse() {
+                Ok(uid) => Predicate::User(uid),
+                Err(_) => Predicate::User(users::user_id(arg).ok_or_else(|| Error::UnknownUser(arg.to_string()))?),
+            },
+            ("group", _) => match arg.parse() {
+                Ok(gid) => Predicate::Group(gid),
+                Err(_) => Predicate::Group(users::group_id(arg).ok_or_else(|| Error::UnknownGroup(arg.to_string()))?),
+            },
+            _ => unreachable!(),
+        })
+    }
+}
+
+/// Parses permission bits, either in octal like `4755`, or symbolic like `u+x,go=r`, where `+` and `=` both stand
+/// for the bits that follow. `s` is only accepted for `u` and `g`, and `t` only for `o`.
+fn parse_mode(arg: &str) -> Option<u32> {
+    if arg.bytes().all(|b| b.is_ascii_digit()) {
+        return u32::from_str_radix(arg, 8).ok().filter(|&mode| mode <= 0o7777);
+    }
+    let mut mode = 0;
+    for clause in arg.split(',') {
+        let op = clause.find(['+', '='])?;
+        let (who, perms) = (&clause[..op], &clause[op + 1..]);
+        let who = if who.is_empty() { "a" } else { who };
+        if perms.is_empty() {
+            return None;
+        }
+        for w in who.chars() {
+            let classes: &[char] = if w == 'a' { &['u', 'g', 'o'] } else { &[w] };
+            for &class in classes {
+                let (shift, special) = match class {
+                    'u' => (6, 0o4000),
+                    'g' => (3, 0o2000),
+                    'o' => (0, 0o1000),
+                    _ => return None,
+                };
+                mode |= perms_bits(perms, shift, special)?;
+            }
+        }
+    }
+    Some(mode)
+}
+
+/// The bits of symbolic permissions like `rwx` for one class of users, whose bits start at `shift` and whose
+/// setuid, setgid or sticky bit is `special`, spelled `t` for others and `s` for the owner and the group.
+fn perms_bits(perms: &str, shift: u32, special: u32) -> Option<u32> {
+    let mut bits = 0;
+    for p in perms.chars() {
+        bits |= match p {
+            'r' => 4 << shift,
+            'w' => 2 << shift,
+            'x' => 1 << shift,
+            's' if shift > 0 => special,
+            't' if shift == 0 => special,
+            _ => return None,
+        };
+    }
+    Some(bits)
+}
+
+/// Parses the argument of `hash eq`, telling MD5 and SHA-256 hashes apart by length if no algorithm is given.
+fn parse_hash(arg: &str) -> Option<Predicate> {
+    let (algorithm, hex) = match arg.split_once(':') {
+        Some((name, hex)) => (Algorithm::from_name(name)?, hex),
+        None if arg.len() == 32 => (Algorithm::Md5, arg),
+        None if arg.len() == 64 => (Algorithm::Sha256, arg),
+        None => return None,
+    };
+    let len = if algorithm == Algorithm::Md5 { 32 } else { 64 };
+    if hex.len() != len || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(Predicate::Hash(algorithm, hex.to_ascii_lowercase()))
+}
+
+impl Matcher {
+    pub fn parse(tokens: &[String], content: ContentOptions) -> Result<Self> {
+        let mut parser = Parser { tokens: tokens.iter().peekable() };
+        let expr = parser.or()?;
+        match parser.tokens.next() {
+            Some(got) => Err(Error::Unexpected { expected: "\"and\" or \"or\"", got: got.clone() }),
+            None => {
+                let mut algorithms = Vec::new();
+                expr.algorithms(&mut algorithms);
+                let hashes = Arc::new(Hashes::new(algorithms));
+                Ok(Self { expr, content, users: Arc::default(), hashes })
+            },
+        }
+    }
+
+    /// The hash algorithms of the `hash eq` predicates.
+    pub fn algorithms(&self) -> Vec<Algorithm> {
+        let mut algorithms = Vec::new();
+        self.expr.algorithms(&mut algorithms);
+        algorithms
+    }
+
+    /// Makes `hash eq` use the digests of `hashes`, which must include the algorithms of the expression, so that the
+    /// files are hashed once for both the expression and the output.
+    pub fn share_hashes(&mut self, hashes: Arc<Hashes>) {
+        self.hashes = hashes;
+    }
+
+    /// Makes `nouser` and `nogroup` look up the IDs in `users`, so that the output finds the names they cached.
+    pub fn share_users(&mut self, users: Arc<Mutex<Users>>) {
+        self.users = users;
+    }
+
+    pub fn matches(&self, entry: &DirEntry) -> bool {
+        let content = OnceCell::new();
+        self.expr.eval(entry, self, &|| content.get_or_init(|| read_content(entry, self.content)).as_deref())
+    }
+}
+
+/// Reads a regular file for `content` predicates, or returns `None` if it is not one, is too large, looks binary,
+/// or cannot be read.
+fn read_content(entry: &DirEntry, opts: ContentOptions) -> Option<Vec<u8>> {
+    let metadata = entry.metadata().ok()?;
+    if !metadata.is_file() || metadata.len() > opts.max_size {
+        return None;
+    }
+    let mut content = Vec::with_capacity(metadata.len() as usize);
+    // The file may have grown since its metadata was read.
+    fs::File::open(entry.path()).ok()?.take(opts.max_size + 1).read_to_end(&mut content).ok()?;
+    if content.len() as u64 > opts.max_size {
+        return None;
+    }
+    if !opts.binary && content[..content.len().min(BINARY_CHECK)].contains(&0) {
+        return None;
+    }
+    Some(content)
+}
+
+impl Expr {
+    fn algorithms(&self, algorithms: &mut Vec<Algorithm>) {
+        match self {
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                a.algorithms(algorithms);
+                b.algorithms(algorithms);
+            },
+            Expr::Not(a) => a.algorithms(algorithms),
+            Expr::Predicate(Predicate::Hash(algorithm, _)) if !algorithms.contains(algorithm) => algorithms.push(*algorithm),
+            Expr::Predicate(_) => {},
+        }
+    }
+
+    fn eval<'c>(&self, entry: &DirEntry, matcher: &Matcher, content: &dyn Fn() -> Option<&'c [u8]>) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(entry, matcher, content) && b.eval(entry, matcher, content),
+            Expr::Or(a, b) => a.eval(entry, matcher, content) || b.eval(entry, matcher, content),
+            Expr::Not(a) => !a.eval(entry, matcher, content),
+            Expr::Predicate(Predicate::Name(re)) => re.is_match(&entry.file_name().to_string_lossy()),
+            Expr::Predicate(Predicate::Type(name)) => fields::type_name(entry.file_type()) == *name,
+            Expr::Predicate(Predicate::SizeGt(size)) => entry.metadata().is_ok_and(|m| m.len() > *size),
+            Expr::Predicate(Predicate::Content(re)) => content().is_some_and(|content| re.is_match(content)),
+            Expr::Predicate(Predicate::Hash(algorithm, hex)) => {
+                matcher.hashes.digests(entry).as_ref().is_ok_and(|digests| digests.get(*algorithm) == Some(hex))
+            },
+            Expr::Predicate(Predicate::Access(access)) => accessible(entry, *access),
+            Expr::Predicate(predicate) => {
+                let Ok(metadata) = entry.metadata() else {
+                    return false;
+                };
+                owner_and_mode(predicate, metadata, &matcher.users)
+            },
+        }
+    }
+}
+
+/// Evaluates the predicates on the permission bits and owner of a file.
+#[cfg(unix)]
+fn owner_and_mode(predicate: &Predicate, metadata: &fs::Metadata, users: &Mutex<Users>) -> bool {
+    let mode = metadata.mode() & 0o7777;
+    match predicate {
+        Predicate::Perm(PermTest::Has, bits) => mode & bits == *bits,
+        Predicate::Perm(PermTest::Eq, bits) => mode == *bits,
+        Predicate::Perm(PermTest::Any, bits) => mode & bits != 0,
+        Predicate::User(uid) => metadata.uid() == *uid,
+        Predicate::Group(gid) => metadata.gid() == *gid,
+        Predicate::NoUser => !users.lock().unwrap().has_user(metadata.uid()),
+        Predicate::NoGroup => !users.lock().unwrap().has_group(metadata.gid()),
+        _ => unreachable!(),
+    }
+}
+
+/// Owners and Unix permission bits do not exist on this platform, so these predicates never match.
+#[cfg(not(unix))]
+fn owner_and_mode(_predicate: &Predicate, _metadata: &fs::Metadata, _users: &Mutex<Users>) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn accessible(entry: &DirEntry, access: Access) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(entry.path().as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mode = match access {
+        Access::Read => libc::R_OK,
+        Access::Write => libc::W_OK,
+        Access::Execute => libc::X_OK,
+    };
+    // SAFETY: `path` is a NUL-terminated string.
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
+
+#[cfg(not(unix))]
+fn accessible(entry: &DirEntry, access: Access) -> bool {
+    match access {
+        Access::Read => fs::File::open(entry.path()).is_ok() || entry.file_type().is_dir(),
+        Access::Write => entry.metadata().is_ok_and(|m| !m.permissions().readonly()),
+        Access::Execute => entry.file_type().is_dir(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: ContentOptions = ContentOptions { binary: false, max_size: 1000 };
+
+    fn parse(expr: &str) -> Result<Matcher> {
+        let tokens: Vec<String> = expr.split(' ').map(String::from).collect();
+        Matcher::parse(&tokens, CONTENT)
+    }
+
+    fn matching(expr: &str, content: ContentOptions) -> Vec<String> {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        fs::create_dir(root.path().join("src")).unwrap();
+        fs::write(root.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(root.path().join("src/lib.rs"), "pub fn f() {}\n").unwrap();
+        fs::write(root.path().join("data.bin"), b"\0fn main").unwrap();
+        fs::write(root.path().join("big.rs"), format!("fn main() {{}}\n{}", " ".repeat(1000))).unwrap();
+        let tokens: Vec<String> = expr.split(' ').map(String::from).collect();
+        let matcher = Matcher::parse(&tokens, content).unwrap();
+        let walker = bfind::WalkBuilder::new(root.path()).filter(move |entry| matcher.matches(entry)).build().unwrap();
+        let mut names: Vec<_> = walker.map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn globs() {
+        let matches = |glob, name| regex(&glob_to_regex(glob)).unwrap().is_match(name);
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rs.bak"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a.c", "abc"));
+        assert!(matches("[!a-c]x", "dx"));
+        assert!(!matches("[!a-c]x", "bx"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("a[", "a["));
+        assert!(matches("[]", "[]"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("name glob *.rs type is file or not ( size gt 1k )").is_ok());
+        assert!(matches!(parse("name"), Err(Error::UnexpectedEnd(_))));
+        assert!(matches!(parse("name is x"), Err(Error::Unexpected { .. })));
+        assert!(matches!(parse("type is directory"), Err(Error::UnknownType(_))));
+        assert!(matches!(parse("size gt lots"), Err(Error::InvalidSize(_))));
+        assert!(matches!(parse("hash eq abc"), Err(Error::InvalidHash(_))));
+        assert!(matches!(parse("hash eq sha1:0123"), Err(Error::InvalidHash(_))));
+        assert!(matches!(parse("hash eq md5:900150983cd24fb0d6963f7d28e17f7"), Err(Error::InvalidHash(_))));
+        assert!(matches!(parse("name match ("), Err(Error::Regex { .. })));
+        assert!(matches!(parse("( type is dir"), Err(Error::UnexpectedEnd(_))));
+        assert!(matches!(parse("type is dir )"), Err(Error::Unexpected { .. })));
+        assert!(matches!(parse("perm has o+s"), Err(Error::InvalidMode(_))));
+        assert!(matches!(parse("perm any a+t"), Err(Error::InvalidMode(_))));
+    }
+
+    #[test]
+    fn modes() {
+        assert_eq!(parse_mode("644"), Some(0o644));
+        assert_eq!(parse_mode("4000"), Some(0o4000));
+        assert_eq!(parse_mode("17777"), None);
+        assert_eq!(parse_mode("u+x"), Some(0o100));
+        assert_eq!(parse_mode("u=rw,go+r"), Some(0o644));
+        assert_eq!(parse_mode("+x"), Some(0o111));
+        assert_eq!(parse_mode("ug+s,o+t"), Some(0o7000));
+        assert_eq!(parse_mode("o+s"), None);
+        assert_eq!(parse_mode("u+t"), None);
+        assert_eq!(parse_mode("a+t"), None);
+        assert_eq!(parse_mode("+s"), None);
+        assert_eq!(parse_mode("u-x"), None);
+        assert_eq!(parse_mode("u+"), None);
+        assert_eq!(parse_mode("z+r"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn permissions_and_owners() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        for (name, mode) in [("run", 0o4755), ("doc", 0o644), ("secret", 0o600)] {
+            let path = root.path().join(name);
+            fs::write(&path, "").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        }
+        let uid = fs::metadata(root.path()).unwrap().uid();
+        let matching = |expr: &str| {
+            let tokens: Vec<String> = expr.split(' ').map(String::from).collect();
+            let matcher = Matcher::parse(&tokens, CONTENT).unwrap();
+            let walker = bfind::WalkBuilder::new(root.path()).filter(move |entry| matcher.matches(entry)).build().unwrap();
+            let mut names: Vec<_> = walker.map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(matching("perm has 4000"), ["run"]);
+        assert_eq!(matching("perm eq 644"), ["doc"]);
+        assert_eq!(matching("perm any go+r"), ["doc", "run"]);
+        assert_eq!(matching("perm has u+x"), ["run"]);
+        assert_eq!(matching(&format!("user is {} and not perm any o+r", uid)), ["secret"]);
+        assert_eq!(matching("nouser or nogroup"), Vec::<String>::new());
+        assert_eq!(matching("executable"), ["run"]);
+        assert_eq!(matching("readable and writable").len(), 3);
+        assert!(matches!(parse("user is no-such-user"), Err(Error::UnknownUser(_))));
+        assert!(matches!(parse("group is no-such-group"), Err(Error::UnknownGroup(_))));
+        assert!(matches!(parse("perm has u-x"), Err(Error::InvalidMode(_))));
+    }
+
+    #[test]
+    fn evaluate() {
+        assert_eq!(matching("name glob *.rs and not name match ^m", CONTENT), ["big.rs", "lib.rs"]);
+        assert_eq!(matching("type is dir or name glob *.bin", CONTENT), ["data.bin", "src"]);
+        assert_eq!(matching("content contains fn", CONTENT), ["lib.rs", "main.rs"]);
+        // The MD5 hash of "fn main() {}\n".
+        assert_eq!(matching("hash eq md5:639E04C270FEF8589636E0416761A67B", CONTENT), ["main.rs"]);
+        assert_eq!(matching("content match ^fn.main", ContentOptions { binary: true, max_size: 2000 }),
+                   ["big.rs", "main.rs"]);
+        assert_eq!(matching("content contains main", ContentOptions { binary: true, max_size: 1000 }),
+                   ["data.bin", "main.rs"]);
+    }
+}
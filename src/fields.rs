@@ -0,0 +1,323 @@
+//! The fields of an entry that `print` templates and `--json` output.
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use bfind::DirEntry;
+
+use crate::attached;
+use crate::hash::Algorithm;
+use crate::hash::Digests;
+use crate::json;
+use crate::json::Value;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Path,
+    FullPath,
+    Name,
+    Depth,
+    Root,
+    Type,
+    Size,
+    Mode,
+    Uid,
+    Gid,
+    Atime,
+    Mtime,
+    Ctime,
+    Ino,
+    Dev,
+    Target,
+    Sha256,
+    Blake3,
+    Md5,
+}
+
+impl Field {
+    pub const ALL: &'static [Field] = &[
+        Field::Path,
+        Field::FullPath,
+        Field::Name,
+        Field::Depth,
+        Field::Root,
+        Field::Type,
+        Field::Size,
+        Field::Mode,
+        Field::Uid,
+        Field::Gid,
+        Field::Atime,
+        Field::Mtime,
+        Field::Ctime,
+        Field::Ino,
+        Field::Dev,
+        Field::Target,
+        Field::Sha256,
+        Field::Blake3,
+        Field::Md5,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Path => "path",
+            Field::FullPath => "fullpath",
+            Field::Name => "name",
+            Field::Depth => "depth",
+            Field::Root => "root",
+            Field::Type => "type",
+            Field::Size => "size",
+            Field::Mode => "mode",
+            Field::Uid => "uid",
+            Field::Gid => "gid",
+            Field::Atime => "atime",
+            Field::Mtime => "mtime",
+            Field::Ctime => "ctime",
+            Field::Ino => "ino",
+            Field::Dev => "dev",
+            Field::Target => "target",
+            Field::Sha256 => "sha256",
+            Field::Blake3 => "blake3",
+            Field::Md5 => "md5",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Field> {
+        Field::ALL.iter().copied().find(|field| field.name() == name)
+    }
+
+    /// Whether the field is read from the metadata of the entry, which may take a system call.
+    pub fn needs_metadata(self) -> bool {
+        matches!(self, Field::Size | Field::Mode | Field::Uid | Field::Gid | Field::Atime | Field::Mtime | Field::Ctime)
+    }
+
+    /// The hash algorithm of the field, if it is the hash of the contents of the entry.
+    pub fn algorithm(self) -> Option<Algorithm> {
+        Algorithm::from_name(self.name())
+    }
+}
+
+/// The value of a field of an entry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue<'a> {
+    Path(Cow<'a, Path>),
+    Text(&'a str),
+    UInt(u64),
+    Int(i64),
+
+    /// Permission bits, printed in octal.
+    Mode(u32),
+
+    /// The field is not available, because the metadata could not be read, the platform does not have it, or it does
+    /// not apply to the entry, like the target of a file that is not a symbolic link.
+    Missing,
+}
+
+impl FieldValue<'_> {
+    /// Whether the value is a number, which templates align to the right by default.
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, FieldValue::UInt(_) | FieldValue::Int(_) | FieldValue::Mode(_))
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            FieldValue::Path(path) => json::os_str(path.as_os_str()),
+            FieldValue::Text(s) => Value::from(*s),
+            FieldValue::UInt(n) => Value::UInt(*n),
+            FieldValue::Int(n) => Value::Int(*n),
+            FieldValue::Mode(mode) => Value::UInt(*mode as u64),
+            FieldValue::Missing => Value::Null,
+        }
+    }
+}
+
+impl fmt::Display for FieldValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FieldValue::Path(path) => write!(f, "{}", path.display()),
+            FieldValue::Text(s) => f.write_str(s),
+            FieldValue::UInt(n) => write!(f, "{}", n),
+            FieldValue::Int(n) => write!(f, "{}", n),
+            FieldValue::Mode(mode) => write!(f, "{:04o}", mode),
+            FieldValue::Missing => Ok(()),
+        }
+    }
+}
+
+/// The name of the type of an entry, as printed and accepted by `type is`.
+pub fn type_name(file_type: fs::FileType) -> &'static str {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            return "fifo";
+        } else if file_type.is_socket() {
+            return "socket";
+        } else if file_type.is_block_device() {
+            return "block";
+        } else if file_type.is_char_device() {
+            return "char";
+        }
+    }
+    if file_type.is_dir() {
+        "dir"
+    } else if file_type.is_symlink() {
+        "link"
+    } else if file_type.is_file() {
+        "file"
+    } else {
+        "unknown"
+    }
+}
+
+/// Seconds since the Unix epoch, negative before it.
+fn unix_time(time: std::io::Result<SystemTime>) -> FieldValue<'static> {
+    match time.map(|t| t.duration_since(UNIX_EPOCH)) {
+        Ok(Ok(d)) => FieldValue::Int(d.as_secs() as i64),
+        Ok(Err(e)) => FieldValue::Int(-(e.duration().as_secs() as i64)),
+        Err(_) => FieldValue::Missing,
+    }
+}
+
+#[cfg(unix)]
+fn unix_field(field: Field, metadata: &fs::Metadata) -> FieldValue<'static> {
+    match field {
+        Field::Mode => FieldValue::Mode(metadata.mode() & 0o7777),
+        Field::Uid => FieldValue::UInt(metadata.uid() as u64),
+        Field::Gid => FieldValue::UInt(metadata.gid() as u64),
+        Field::Ctime => FieldValue::Int(metadata.ctime()),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(not(unix))]
+fn unix_field(_field: Field, _metadata: &fs::Metadata) -> FieldValue<'static> {
+    FieldValue::Missing
+}
+
+/// Gets a field of an entry. `shown` is the path as printed, `metadata` is the metadata of the entry if it could be
+/// read, and `digests` are the hashes of its contents that were computed.
+pub fn value<'a>(field: Field, entry: &'a DirEntry, shown: &'a Path, metadata: Option<&fs::Metadata>,
+                 digests: &'a Digests) -> FieldValue<'a> {
+    if let Some(algorithm) = field.algorithm() {
+        return digests.get(algorithm).map_or(FieldValue::Missing, FieldValue::Text);
+    }
+    match field {
+        Field::Path => FieldValue::Path(Cow::Borrowed(shown)),
+        Field::FullPath => FieldValue::Path(Cow::Borrowed(entry.path())),
+        Field::Name => FieldValue::Path(Cow::Borrowed(Path::new(entry.file_name()))),
+        Field::Depth => FieldValue::UInt(entry.depth() as u64),
+        Field::Root => FieldValue::UInt(entry.root_index() as u64),
+        Field::Type => FieldValue::Text(type_name(entry.file_type())),
+        Field::Ino => FieldValue::UInt(entry.ino()),
+        Field::Dev => FieldValue::UInt(entry.dev()),
+        Field::Target => match link_target(entry) {
+            Some(target) => FieldValue::Path(Cow::Borrowed(target)),
+            None => FieldValue::Missing,
+        },
+        _ => {
+            let Some(metadata) = metadata else {
+                return FieldValue::Missing;
+            };
+            match field {
+                Field::Size => FieldValue::UInt(metadata.len()),
+                Field::Atime => unix_time(metadata.accessed()),
+                Field::Mtime => unix_time(metadata.modified()),
+                _ => unix_field(field, metadata),
+            }
+        },
+    }
+}
+
+/// The target of a symbolic link, or `None` if the entry is not one or the link cannot be read. Called by a filter of
+/// the walker for the formats that print it, so that the link is read in the worker threads.
+pub fn link_target(entry: &DirEntry) -> Option<&Path> {
+    attached::of(entry).target.get_or_init(|| {
+        if entry.is_symlink() {
+            entry.read_link().ok().map(Path::to_path_buf)
+        } else {
+            None
+        }
+    }).as_deref()
+}
+
+/// The fields of a `--json` record, in order.
+pub const JSON_FIELDS: &[Field] = &[
+    Field::Path,
+    Field::Name,
+    Field::Depth,
+    Field::Type,
+    Field::Size,
+    Field::Mode,
+    Field::Uid,
+    Field::Gid,
+    Field::Atime,
+    Field::Mtime,
+    Field::Ctime,
+    Field::Target,
+    Field::Root,
+];
+
+/// The `--json` record of an entry.
+pub fn json_record(entry: &DirEntry, shown: &Path, metadata: Option<&fs::Metadata>) -> Value {
+    let digests = Digests::default();
+    Value::object(JSON_FIELDS.iter().map(|&field| (field.name(), value(field, entry, shown, metadata, &digests).to_json())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli;
+
+    #[test]
+    fn fields_are_documented() {
+        for field in Field::ALL {
+            let placeholder = format!("{{{}}}", field.name());
+            assert!(cli::PLACEHOLDERS.iter().any(|spec| spec.syntax == placeholder), "{} is not documented", placeholder);
+            assert_eq!(Field::from_name(field.name()), Some(*field));
+        }
+    }
+
+    #[test]
+    fn record() {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        fs::write(root.path().join("f"), "hello").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("f", root.path().join("l")).unwrap();
+        let mut walker = bfind::WalkBuilder::new(root.path()).build().unwrap();
+        let mut records = Vec::new();
+        for entry in walker.by_ref() {
+            let entry = entry.unwrap();
+            let shown = entry.path().strip_prefix(root.path()).unwrap().to_path_buf();
+            records.push(json_record(&entry, &shown, entry.metadata().ok()).to_string());
+        }
+        records.sort();
+        assert!(records[0].starts_with(r#"{"path":"f","name":"f","depth":1,"type":"file","size":5,"#), "{}", records[0]);
+        assert!(records[0].ends_with(r#","target":null,"root":0}"#), "{}", records[0]);
+        #[cfg(unix)]
+        assert!(records[1].contains(r#""type":"link","#) && records[1].contains(r#""target":"f","#), "{}", records[1]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn link_target_attached() {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        fs::write(root.path().join("f"), "hello").unwrap();
+        std::os::unix::fs::symlink("f", root.path().join("l")).unwrap();
+        let walker = bfind::WalkBuilder::new(root.path()).filter(|entry| {
+            link_target(entry);
+            true
+        });
+        let mut entries: Vec<_> = walker.build().unwrap().map(Result::unwrap).collect();
+        entries.sort_by(|a, b| a.path().cmp(b.path()));
+        // Read by the filter, so the printer does not need the link anymore.
+        fs::remove_file(root.path().join("l")).unwrap();
+        assert_eq!(link_target(&entries[0]), None);
+        assert_eq!(link_target(&entries[1]), Some(Path::new("f")));
+    }
+}
@@ -0,0 +1,177 @@
+//! Hashes of file contents for the `{sha256}`, `{blake3}` and `{md5}` placeholders and the `hash eq` predicate.
+//!
+//! The digests of an entry are computed in a worker thread, by `hash eq` or else by the last filter of the walker
+//! for the entries that passed the expression, and attached to the entry until it is printed. A file is read once
+//! for all the algorithms of the expression and the output.
+
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use bfind::DirEntry;
+use md5::Digest as _;
+
+use crate::attached;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+    Md5,
+}
+
+impl Algorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Blake3 => "blake3",
+            Algorithm::Md5 => "md5",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        [Algorithm::Sha256, Algorithm::Blake3, Algorithm::Md5].into_iter().find(|algorithm| algorithm.name() == name)
+    }
+}
+
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Md5(md5::Md5),
+}
+
+impl Hasher {
+    fn new(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            Algorithm::Md5 => Hasher::Md5(md5::Md5::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            },
+            Hasher::Md5(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => hex(&hasher.finalize()),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Hasher::Md5(hasher) => hex(&hasher.finalize()),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(s, "{:02x}", byte).unwrap();
+    }
+    s
+}
+
+/// The hashes of a file in lowercase hexadecimal, by algorithm.
+#[derive(Debug, Default, PartialEq)]
+pub struct Digests(Vec<(Algorithm, String)>);
+
+impl Digests {
+    pub fn get(&self, algorithm: Algorithm) -> Option<&str> {
+        self.0.iter().find(|(a, _)| *a == algorithm).map(|(_, hex)| hex.as_str())
+    }
+}
+
+/// Reads a file once and hashes it with each of `algorithms`.
+pub fn hash_file(path: &Path, algorithms: &[Algorithm]) -> io::Result<Digests> {
+    let mut file = fs::File::open(path)?;
+    let mut hashers: Vec<_> = algorithms.iter().map(|&algorithm| Hasher::new(algorithm)).collect();
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hashers.iter_mut().for_each(|hasher| hasher.update(&buf[..n])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Digests(algorithms.iter().copied().zip(hashers.into_iter().map(Hasher::finalize)).collect()))
+}
+
+/// Whether the hashes of an entry can be computed, which is only the case for regular files.
+pub fn is_hashable(entry: &DirEntry) -> bool {
+    entry.metadata().is_ok_and(|m| m.is_file())
+}
+
+/// The hash algorithms needed by the expression and the output.
+#[derive(Debug)]
+pub struct Hashes {
+    algorithms: Vec<Algorithm>,
+}
+
+impl Hashes {
+    pub fn new(algorithms: Vec<Algorithm>) -> Self {
+        Self { algorithms }
+    }
+
+    /// The digests of an entry, which are empty if it is not a regular file. They are computed on the first call and
+    /// attached to the entry.
+    pub fn digests<'e>(&self, entry: &'e DirEntry) -> &'e io::Result<Digests> {
+        attached::of(entry).digests.get_or_init(|| {
+            if is_hashable(entry) {
+                hash_file(entry.path(), &self.algorithms)
+            } else {
+                Ok(Digests::default())
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_hashes() {
+        let dir = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        let path = dir.path().join("f");
+        fs::write(&path, "abc").unwrap();
+        let digests = hash_file(&path, &[Algorithm::Md5, Algorithm::Sha256, Algorithm::Blake3]).unwrap();
+        assert_eq!(digests.get(Algorithm::Md5), Some("900150983cd24fb0d6963f7d28e17f72"));
+        assert_eq!(digests.get(Algorithm::Sha256),
+                   Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"));
+        assert_eq!(digests.get(Algorithm::Blake3),
+                   Some("6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"));
+        assert_eq!(hash_file(&path, &[Algorithm::Md5]).unwrap().get(Algorithm::Sha256), None);
+    }
+
+    #[test]
+    fn attached_digests() {
+        let dir = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        fs::write(dir.path().join("f"), "abc").unwrap();
+        fs::create_dir(dir.path().join("d")).unwrap();
+        // The same file found from two overlapping roots.
+        let walker = bfind::WalkBuilder::new(dir.path()).add_root(dir.path()).max_depth(1).build().unwrap();
+        let hashes = Hashes::new(vec![Algorithm::Md5]);
+        for entry in walker {
+            let entry = entry.unwrap();
+            let digests = hashes.digests(&entry).as_ref().unwrap();
+            if entry.file_type().is_dir() {
+                assert_eq!(digests, &Digests::default());
+                continue;
+            }
+            assert_eq!(digests.get(Algorithm::Md5), Some("900150983cd24fb0d6963f7d28e17f72"));
+            // Computed once: the file is not read again.
+            fs::write(entry.path(), "changed").unwrap();
+            assert_eq!(hashes.digests(&entry).as_ref().unwrap().get(Algorithm::Md5),
+                       Some("900150983cd24fb0d6963f7d28e17f72"));
+            fs::write(entry.path(), "abc").unwrap();
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Breadth-first directory traversal with a pool of worker threads.
+//!
+//! [`WalkBuilder`] configures a traversal and starts a [`BfsWalker`], which yields the entries it finds, or runs it
+//! with a [`Visitor`] that is called from the worker threads and decides whether to descend into each directory.
+//! Each worker thread keeps the directories it has yet to read in a bounded work-stealing deque that idle workers
+//! steal from, and moves the rest to a queue that spills to a temporary file when it grows large, which is available
+//! as [`path_queue::PathQueue`].
+
+mod checkpoint;
+mod deque;
+pub mod path_queue;
+mod sync;
+mod visit;
+mod walk;
+
+pub use visit::Visit;
+pub use visit::Visitor;
+pub use walk::BfsWalker;
+pub use walk::DirEntry;
+pub use walk::Error;
+pub use walk::Monitor;
+pub use walk::Paused;
+pub use walk::Progress;
+pub use walk::Result;
+pub use walk::Stats;
+pub use walk::WalkBuilder;
@@ -0,0 +1,218 @@
+//! The `-l` output, like `ls -l`: type and permissions, link count, owner, group, size, modification time and path.
+//!
+//! Entries are printed as they are found, so the columns cannot be sized for all of them up front. Each column is
+//! as wide as its widest value so far instead, so it only moves when a wider value comes. The values are formatted,
+//! and owners looked up, in the worker threads by [`prefetch`].
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use bfind::DirEntry;
+
+use crate::attached;
+use crate::color::Colors;
+use crate::fields;
+use crate::users::Users;
+
+/// Columns whose width adapts to the values printed so far.
+const NLINK: usize = 0;
+const USER: usize = 1;
+const GROUP: usize = 2;
+const SIZE: usize = 3;
+
+pub struct Long {
+    widths:     [usize; 4],
+}
+
+impl Default for Long {
+    fn default() -> Self {
+        let mut widths = [0; 4];
+        // Human-readable sizes take at most 4 characters.
+        widths[SIZE] = 4;
+        Self { widths }
+    }
+}
+
+/// Formats the columns of an entry before its path and attaches them to it. Called by a filter of the walker, so that
+/// the owner names are looked up in the worker threads, in the cache the expression uses too.
+pub fn prefetch<'e>(entry: &'e DirEntry, users: &Mutex<Users>) -> &'e [String; 6] {
+    attached::of(entry).long.get_or_init(|| match entry.metadata() {
+        Ok(metadata) => columns(metadata, users),
+        Err(_) => ["?".repeat(10), "?".to_string(), "?".to_string(), "?".to_string(), "?".to_string(), "?".repeat(16)],
+    })
+}
+
+#[cfg(unix)]
+fn columns(metadata: &fs::Metadata, users: &Mutex<Users>) -> [String; 6] {
+    let (user, group) = {
+        let mut users = users.lock().unwrap();
+        (users.user_name(metadata.uid()).to_string(), users.group_name(metadata.gid()).to_string())
+    };
+    [
+        mode_string(metadata.file_type(), metadata.mode()),
+        metadata.nlink().to_string(),
+        user,
+        group,
+        human_size(metadata.len()),
+        local_time(metadata.mtime()),
+    ]
+}
+
+#[cfg(not(unix))]
+fn columns(metadata: &fs::Metadata, _users: &Mutex<Users>) -> [String; 6] {
+    let kind = if metadata.is_dir() { 'd' } else if metadata.is_symlink() { 'l' } else { '-' };
+    let perms = if metadata.permissions().readonly() { "r--r--r--" } else { "rw-rw-rw-" };
+    let mtime = metadata.modified().ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or_else(|| "?".to_string(), |d| d.as_secs().to_string());
+    [format!("{}{}", kind, perms), "1".to_string(), "-".to_string(), "-".to_string(), human_size(metadata.len()), mtime]
+}
+
+/// Formats a size with a binary suffix and at most 3 significant digits, rounding up, like `ls -h`.
+pub fn human_size(size: u64) -> String {
+    const SUFFIXES: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+    if size < 1024 {
+        return size.to_string();
+    }
+    let mut value = size as f64;
+    for suffix in SUFFIXES {
+        value /= 1024.0;
+        // Round up at the precision printed, to one decimal below 10.
+        let rounded = if value < 10.0 { (value * 10.0).ceil() / 10.0 } else { value.ceil() };
+        if rounded < 1024.0 {
+            return if rounded < 10.0 { format!("{:.1}{}", rounded, suffix) } else { format!("{:.0}{}", rounded, suffix) };
+        }
+    }
+    unreachable!()
+}
+
+/// The type and permissions of a file, like `drwxr-xr-x`.
+#[cfg(unix)]
+pub fn mode_string(file_type: fs::FileType, mode: u32) -> String {
+    use std::os::unix::fs::FileTypeExt;
+    let kind = if file_type.is_dir() {
+        'd'
+    } else if file_type.is_symlink() {
+        'l'
+    } else if file_type.is_fifo() {
+        'p'
+    } else if file_type.is_socket() {
+        's'
+    } else if file_type.is_block_device() {
+        'b'
+    } else if file_type.is_char_device() {
+        'c'
+    } else {
+        '-'
+    };
+    let mut s = String::with_capacity(10);
+    s.push(kind);
+    for (shift, special, special_char) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = mode >> shift;
+        s.push(if bits & 4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 2 != 0 { 'w' } else { '-' });
+        s.push(match (bits & 1 != 0, mode & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    s
+}
+
+/// Formats seconds since the Unix epoch in local time as `YYYY-MM-DD HH:MM`.
+#[cfg(unix)]
+pub fn local_time(secs: i64) -> String {
+    let time = secs as libc::time_t;
+    let mut tm = std::mem::MaybeUninit::<libc::tm>::uninit();
+    // SAFETY: localtime_r fills in `tm` when it returns non-null.
+    let tm = unsafe {
+        if libc::localtime_r(&time, tm.as_mut_ptr()).is_null() {
+            return secs.to_string();
+        }
+        tm.assume_init()
+    };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min)
+}
+
+impl Long {
+    /// Appends the line of an entry to `out`, without a line break. `metadata` is that of the target of a symbolic
+    /// link if the walker follows them, like with `ls -lL`. The path and link target are colored with `colors`. The
+    /// entry must have been passed to [`prefetch`].
+    pub fn render(&mut self, out: &mut String, entry: &DirEntry, shown: &Path, metadata: Option<&fs::Metadata>,
+                  colors: Option<&Colors>) {
+        let [mode, nlink, user, group, size, mtime] = attached::of(entry).long.get()
+            .expect("the columns are prefetched by a filter of the walker");
+        for (i, value) in [nlink, user, group, size].into_iter().enumerate() {
+            self.widths[i] = self.widths[i].max(value.chars().count());
+        }
+        let w = &self.widths;
+        write!(out, "{} {:>nw$} {:<uw$} {:<gw$} {:>sw$} {} ", mode, nlink, user, group, size, mtime,
+               nw = w[NLINK], uw = w[USER], gw = w[GROUP], sw = w[SIZE]).unwrap();
+        match colors {
+            Some(colors) => colors.paint(out, entry, shown, metadata),
+            None => write!(out, "{}", shown.display()).unwrap(),
+        }
+        if entry.is_symlink() {
+            if let Some(target) = fields::link_target(entry) {
+                out.push_str(" -> ");
+                match colors {
+                    Some(colors) => colors.paint_target(out, entry, target),
+                    None => write!(out, "{}", target.display()).unwrap(),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(human_size(0), "0");
+        assert_eq!(human_size(1023), "1023");
+        assert_eq!(human_size(1024), "1.0K");
+        assert_eq!(human_size(1025), "1.1K");
+        assert_eq!(human_size(10 * 1024), "10K");
+        assert_eq!(human_size(1024 * 1024 - 1), "1.0M");
+        assert_eq!(human_size(5 << 30), "5.0G");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn modes() {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        let dir_type = fs::metadata(root.path()).unwrap().file_type();
+        assert_eq!(mode_string(dir_type, 0o755), "drwxr-xr-x");
+        assert_eq!(mode_string(dir_type, 0o1777), "drwxrwxrwt");
+        assert_eq!(mode_string(dir_type, 0o4644), "drwSr--r--");
+        assert_eq!(mode_string(dir_type, 0o2750), "drwxr-s---");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn long_line() {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        std::os::unix::fs::symlink("target", root.path().join("l")).unwrap();
+        let users = Mutex::new(Users::default());
+        let walker = bfind::WalkBuilder::new(root.path()).prefetch_metadata(true).filter(move |entry| {
+            prefetch(entry, &users);
+            true
+        });
+        let entry = walker.build().unwrap().next().unwrap().unwrap();
+        assert!(attached::of(&entry).long.get().is_some());
+        let mut long = Long::default();
+        let mut out = String::new();
+        long.render(&mut out, &entry, Path::new("l"), entry.metadata().ok(), None);
+        assert!(out.starts_with("lrwxrwxrwx 1 "), "{}", out);
+        assert!(out.contains("    6 "), "{}", out);
+        assert!(out.ends_with(" l -> target"), "{}", out);
+    }
+}
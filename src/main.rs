@@ -1,42 +1,110 @@
+use std::collections::BTreeMap;
 use std::collections::VecDeque;
 use std::env;
-use std::fs;
+use std::ffi::OsString;
+use std::fmt::Write as _;
 use std::io;
+use std::io::StdoutLock;
+use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::exit;
-use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::Ordering;
-use std::time::Duration;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::thread;
-use thiserror::Error;
-
-mod path_queue;
-use path_queue::PathQueue;
-
-#[derive(Error, Debug)]
-enum Error {
-    #[error("std::io::Error {{ kind = {} }}: {source}", source.kind())]
-    Io {
-        #[from]
-        source: io::Error
-    },
-
-    #[error("path_queue::Error: {source}")]
-    PathQueue {
-        #[from]
-        source: path_queue::Error
-    },
-}
+use std::time::Duration;
+use std::time::Instant;
+
+use bfind::path_queue::Compression;
+use bfind::path_queue::MemoryBudget;
+use bfind::WalkBuilder;
+
+mod attached;
+
+mod cli;
+use cli::EXIT_FAILURE;
+use cli::EXIT_SUCCESS;
+use cli::EXIT_USAGE;
+use cli::Opt;
+use cli::OptionSpec;
+use cli::Verb;
+
+mod color;
+use color::Colors;
+use color::When;
+
+mod config;
+
+mod du;
+use du::Du;
+
+mod dupes;
+use dupes::Dupes;
+
+mod exec;
+use exec::Exec;
+
+mod expr;
+use expr::Matcher;
+
+mod fields;
+use fields::Field;
+
+mod hash;
+use hash::Digests;
+use hash::Hashes;
+
+mod json;
+
+mod long;
+use long::Long;
+
+mod progress;
+
+mod stats;
+use stats::Summary;
+
+mod table;
+use table::Dialect;
+use table::Table;
+
+mod template;
+use template::Template;
 
-type Result<T> = std::result::Result<T, Error>;
+mod tree;
+use tree::Tree;
+
+mod users;
+use users::Users;
 
 struct Options {
     allow_hidden:       bool,
     follow_links:       bool,
     max_depth:          i32,
     ignores:            Vec<String>,
+    one_file_system:    bool,
     strip_cwd_prefix:   bool,
+    threads:            usize,
+    memory:             Option<u64>,
+    spill_dir:          Option<String>,
+    max_spill:          Option<u64>,
+    no_spill:           bool,
+    spill_compression:  Compression,
+    color:              When,
+    json:               bool,
+    long:               bool,
+    table:              Option<Dialect>,
+    columns:            Vec<fields::Field>,
+    binary:             bool,
+    max_content_size:   u64,
+    tree:               bool,
+    tree_totals:        bool,
+    progress:           bool,
+    stats:              bool,
+    stats_json:         bool,
+    checkpoint:         Option<PathBuf>,
+    checkpoint_interval: Duration,
+    resume:             Option<PathBuf>,
 }
 
 impl Options {
@@ -46,116 +114,441 @@ impl Options {
             follow_links: false,
             max_depth: i32::MAX,
             ignores: Vec::new(),
+            one_file_system: false,
             strip_cwd_prefix: false,
+            threads: 0,
+            memory: None,
+            spill_dir: None,
+            max_spill: None,
+            no_spill: false,
+            spill_compression: Compression::default(),
+            color: When::Auto,
+            json: false,
+            long: false,
+            table: None,
+            columns: table::DEFAULT_COLUMNS.to_vec(),
+            binary: false,
+            max_content_size: 16 << 20,
+            tree: false,
+            tree_totals: false,
+            progress: false,
+            stats: false,
+            stats_json: false,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(60),
+            resume: None,
         }
     }
 }
 
-fn pop_or_steal(queues: &[PathQueue], index: usize) -> Result<Option<PathBuf>> {
-    if let Some(path) = queues[index].pop()? {
-        Ok(Some(path))
-    } else {
-        for (i, queue) in queues.iter().enumerate() {
-            if i != index {
-                if let Some(path) = queue.pop()? {
-                    return Ok(Some(path));
-                }
-            }
-        }
-        Ok(None)
-    }
+fn option_value(prog: &str, spec: &OptionSpec, args: &mut VecDeque<String>) -> String {
+    args.pop_front().unwrap_or_else(|| {
+        eprintln!("{}: missing argument to {}", prog, spec.name());
+        exit(EXIT_USAGE);
+    })
 }
 
-fn push(queues: &[PathQueue], index: usize, path: PathBuf) -> Result<()> {
-    if let Some(mut path) = queues[index].push(path)? {
-        loop {
-            for (i, queue) in queues.iter().enumerate() {
-                if i != index {
-                    if let Some(p) = queue.push(path)? {
-                        path = p;
-                    } else {
-                        return Ok(());
-                    }
+fn apply_option(prog: &str, spec: &OptionSpec, args: &mut VecDeque<String>, opts: &mut Options) {
+    match spec.opt {
+        Opt::Hidden => opts.allow_hidden = true,
+        Opt::FollowLinks => opts.follow_links = true,
+        Opt::Depth => {
+            let depth_str = option_value(prog, spec, args);
+            if let Ok(depth) = depth_str.parse::<i32>() {
+                if depth < 1 {
+                    eprintln!("{}: depth must be > 0", prog);
+                    exit(EXIT_USAGE);
+                }
+                opts.max_depth = depth;
+            } else {
+                eprintln!("{}: unable to parse \"{}\" as i32", prog, &depth_str);
+                exit(EXIT_USAGE);
+            }
+        },
+        Opt::Ignore => {
+            let ignore = option_value(prog, spec, args);
+            opts.ignores = ignore.split(',').map(|s| { s.to_string() }).collect();
+        },
+        Opt::OneFileSystem => opts.one_file_system = true,
+        Opt::StripCwdPrefix => opts.strip_cwd_prefix = true,
+        Opt::Threads => {
+            let threads_str = option_value(prog, spec, args);
+            match threads_str.parse::<usize>() {
+                Ok(threads) if threads > 0 => opts.threads = threads,
+                _ => {
+                    eprintln!("{}: number of threads must be a positive integer, got \"{}\"", prog, &threads_str);
+                    exit(EXIT_USAGE);
+                }
+            }
+        },
+        Opt::Memory => {
+            let size = option_value(prog, spec, args);
+            if let Some(size) = cli::parse_size(&size) {
+                opts.memory = Some(size);
+            } else {
+                eprintln!("{}: unable to parse \"{}\" as a size", prog, &size);
+                exit(EXIT_USAGE);
+            }
+        },
+        Opt::SpillDir => opts.spill_dir = Some(option_value(prog, spec, args)),
+        Opt::MaxSpill => {
+            let size = option_value(prog, spec, args);
+            if let Some(size) = cli::parse_size(&size) {
+                opts.max_spill = Some(size);
+            } else {
+                eprintln!("{}: unable to parse \"{}\" as a size", prog, &size);
+                exit(EXIT_USAGE);
+            }
+        },
+        Opt::NoSpill => opts.no_spill = true,
+        Opt::SpillCompression => {
+            let method = option_value(prog, spec, args);
+            opts.spill_compression = match method.as_str() {
+                "none" => Compression::None,
+                "lz4" => Compression::Lz4,
+                "zstd" => Compression::Zstd,
+                _ => {
+                    eprintln!("{}: unknown compression method \"{}\"", prog, &method);
+                    exit(EXIT_USAGE);
                 }
+            };
+            if !opts.spill_compression.is_available() {
+                eprintln!("{}: {} support is not compiled in", prog, &method);
+                exit(EXIT_USAGE);
             }
-            if let Some(p) = queues[index].push(path)? {
-                path = p;
+        },
+        Opt::Color => {
+            let when = option_value(prog, spec, args);
+            opts.color = When::parse(&when).unwrap_or_else(|| {
+                eprintln!("{}: --color must be auto, always or never, got \"{}\"", prog, &when);
+                exit(EXIT_USAGE);
+            });
+        },
+        Opt::Json => opts.json = true,
+        Opt::Long => opts.long = true,
+        Opt::Csv => opts.table = Some(Dialect::Csv),
+        Opt::Tsv => opts.table = Some(Dialect::Tsv),
+        Opt::Binary => opts.binary = true,
+        Opt::MaxContentSize => {
+            let size = option_value(prog, spec, args);
+            if let Some(size) = cli::parse_size(&size) {
+                opts.max_content_size = size;
             } else {
-                return Ok(());
+                eprintln!("{}: unable to parse \"{}\" as a size", prog, &size);
+                exit(EXIT_USAGE);
+            }
+        },
+        Opt::Tree => opts.tree = true,
+        Opt::TreeTotals => {
+            opts.tree = true;
+            opts.tree_totals = true;
+        },
+        Opt::Columns => {
+            let columns = option_value(prog, spec, args);
+            match table::parse_columns(&columns) {
+                Ok(columns) => opts.columns = columns,
+                Err(name) => {
+                    eprintln!("{}: unknown column \"{}\"", prog, name);
+                    exit(EXIT_USAGE);
+                }
+            }
+        },
+        Opt::Progress => opts.progress = true,
+        Opt::Stats => opts.stats = true,
+        Opt::StatsJson => opts.stats_json = true,
+        Opt::Checkpoint => opts.checkpoint = Some(PathBuf::from(option_value(prog, spec, args))),
+        Opt::CheckpointInterval => {
+            let secs_str = option_value(prog, spec, args);
+            match secs_str.parse::<u64>() {
+                Ok(secs) if secs > 0 => opts.checkpoint_interval = Duration::from_secs(secs),
+                _ => {
+                    eprintln!("{}: checkpoint interval must be a positive integer, got \"{}\"", prog, &secs_str);
+                    exit(EXIT_USAGE);
+                }
             }
+        },
+        Opt::Resume => opts.resume = Some(PathBuf::from(option_value(prog, spec, args))),
+        Opt::NoConfig | Opt::PrintConfig => {},
+        Opt::Help => {
+            println!("{}", cli::help(prog));
+            exit(EXIT_SUCCESS);
+        },
+        Opt::Man => {
+            print!("{}", cli::man_page());
+            exit(EXIT_SUCCESS);
+        },
+    }
+}
+
+/// Splits `--option=VALUE` into `--option` and `VALUE` if the option takes a value, putting the value back in front
+/// of the remaining arguments.
+fn split_inline_value(arg: String, args: &mut VecDeque<String>) -> String {
+    if let Some((name, value)) = arg.split_once('=') {
+        if name.starts_with("--") && cli::find_option(name).is_some_and(|spec| spec.arg.is_some()) {
+            args.push_front(value.to_string());
+            return name.to_string();
         }
     }
-    Ok(())
+    arg
 }
 
-fn breadth_first_traverse(prog: &str, cwd: &Path, opt: &Options, queues: &[PathQueue], index: usize, counter: &AtomicUsize) -> Result<()> {
-    loop {
-        let path = pop_or_steal(queues, index)?;
-        if let Some(path) = path {
-            let entries = fs::read_dir(&path);
-            if let Ok(entries) = entries {
-                for entry in entries {
-                    if let Ok(entry) = entry {
-                        let mut path = entry.path();
-                        if opt.follow_links && path.is_symlink() {
-                            let p = path.read_link();
-                            if let Ok(p) = p {
-                                path = p;
-                            } else {
-                                eprintln!("{}: {}: {}", prog, path.display(), p.unwrap_err());
-                                continue;
-                            }
-                        }
-                        if let Some(file_name) = entry.file_name().to_str() {
-                            if !opt.allow_hidden {
-                                if let Some(first_char) = file_name.chars().next() {
-                                    if first_char == '.' {
-                                        continue;
-                                    }
-                                }
-                            }
-                            if opt.ignores.iter().any(|item| item == file_name) {
-                                continue;
-                            }
-                            if opt.strip_cwd_prefix {
-                                if path.starts_with("./") {
-                                    println!("{}", unsafe { path.strip_prefix("./").unwrap_unchecked() }.display());
-                                } else if path.starts_with(cwd) {
-                                    println!("{}", unsafe { path.strip_prefix(cwd).unwrap_unchecked() }.display());
-                                } else {
-                                    println!("{}", path.display());
-                                }
-                            } else {
-                                println!("{}", path.display());
-                            }
-                            if path.is_dir() {
-                                push(queues, index, path)?;
-                                counter.fetch_add(1, Ordering::Release);
-                            }
-                        } else {
-                            eprintln!("{}: {}: cannot read filename", prog, path.display());
-                        }
-                    } else {
-                        eprintln!("{}: {}: {}", prog, path.display(), entry.unwrap_err());
-                    }
+/// Applies the defaults from the configuration file and `BFIND_OPTS`, which may only contain configurable options.
+fn apply_defaults(prog: &str, opts: &mut Options) {
+    let mut sources = Vec::new();
+    if let Some(path) = config::path() {
+        match config::load(&path) {
+            Ok(args) => sources.push((path.display().to_string(), args)),
+            Err(e) => {
+                eprintln!("{}: {}: {}", prog, path.display(), e);
+                exit(EXIT_USAGE);
+            }
+        }
+    }
+    if let Ok(value) = env::var(config::ENV_VAR) {
+        match config::split_args(&value) {
+            Ok(args) => sources.push((config::ENV_VAR.to_string(), args)),
+            Err(e) => {
+                eprintln!("{}: {}: {}", prog, config::ENV_VAR, e);
+                exit(EXIT_USAGE);
+            }
+        }
+    }
+    for (source, args) in sources {
+        let mut args = VecDeque::from(args);
+        while let Some(arg) = args.pop_front() {
+            let arg = split_inline_value(arg, &mut args);
+            match cli::find_option(&arg) {
+                Some(spec) if spec.configurable => apply_option(prog, spec, &mut args, opts),
+                _ => {
+                    eprintln!("{}: {}: unsupported argument: {}", prog, source, &arg);
+                    exit(EXIT_USAGE);
                 }
-            } else {
-                eprintln!("{}: {}: {}", prog, path.display(), entries.unwrap_err());
             }
-            counter.fetch_sub(1, Ordering::Release);
-        } else if counter.load(Ordering::Acquire) == 0 {
-            break;
-        } else {
-            thread::sleep(Duration::from_millis(7));
         }
     }
-    Ok(())
 }
 
-#[derive(PartialEq, Eq)]
-enum Verb {
-    Print,
-    Exec
+/// How entries are printed.
+enum Format {
+    Path,
+    Template(Template),
+    Json,
+    Table(Table),
+    Long(Long),
+
+    /// Entries are not printed, but passed to a command and its arguments.
+    Exec(Exec),
+
+    /// Entries are collected and printed at the end.
+    Tree(Tree),
+    Du(Du),
+    Dupes(Dupes),
+}
+
+impl Format {
+    fn needs_metadata(&self) -> bool {
+        match self {
+            Format::Path => false,
+            Format::Template(template) => template.needs_metadata(),
+            Format::Exec(exec) => exec.needs_metadata(),
+            Format::Json | Format::Long(_) | Format::Du(_) | Format::Dupes(_) => true,
+            Format::Table(table) => table.needs_metadata(),
+            Format::Tree(tree) => tree.needs_metadata(),
+        }
+    }
+
+    /// Whether the targets of symbolic links are printed.
+    fn needs_link_target(&self) -> bool {
+        match self {
+            Format::Template(template) => template.uses(Field::Target),
+            Format::Exec(exec) => exec.uses(Field::Target),
+            Format::Table(table) => table.uses(Field::Target),
+            Format::Json | Format::Long(_) => true,
+            Format::Path | Format::Tree(_) | Format::Du(_) | Format::Dupes(_) => false,
+        }
+    }
+
+    /// The hash algorithms of the placeholders or columns printed.
+    fn algorithms(&self) -> Vec<hash::Algorithm> {
+        match self {
+            Format::Template(template) => template.algorithms(),
+            Format::Exec(exec) => exec.algorithms(),
+            Format::Table(table) => table.algorithms(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Amount of output held back for the next checkpoint above which a checkpoint is taken early.
+const HELD_OUTPUT_LIMIT: usize = 4 << 20;
+
+/// Standard output. With `--checkpoint`, what is printed is held back and only written just before a checkpoint, so
+/// that every entry written is counted by a checkpoint and a resumed traversal does not print it again.
+struct Output {
+    stdout:     StdoutLock<'static>,
+    held:       Option<Vec<u8>>,
+}
+
+impl Output {
+    fn held_len(&self) -> usize {
+        self.held.as_ref().map_or(0, Vec::len)
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.held {
+            Some(held) => {
+                held.extend_from_slice(buf);
+                Ok(buf.len())
+            },
+            None => self.stdout.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(held) = &mut self.held {
+            self.stdout.write_all(held)?;
+            held.clear();
+        }
+        self.stdout.flush()
+    }
+}
+
+/// Prints the entries found by the walker and reports errors.
+struct Printer<'a> {
+    prog:               &'a str,
+    out:                Output,
+    format:             Format,
+    colors:             Option<Arc<Colors>>,
+
+    /// Reused for each line.
+    line:               String,
+    strip_cwd_prefix:   bool,
+    cwd:                &'a Path,
+    status:             i32,
+
+    /// Number of errors by kind, for `--stats`.
+    errors:             BTreeMap<String, u64>,
+
+    /// The algorithms of the placeholders or columns, whose digests are attached to the entries in the worker threads.
+    hashes:             Option<Arc<Hashes>>,
+}
+
+impl Printer<'_> {
+    fn print(&mut self, entry: bfind::Result<bfind::DirEntry>) {
+        match entry {
+            Ok(entry) => {
+                let metadata = if self.format.needs_metadata() || self.colors.is_some() {
+                    entry.metadata().map_err(|e| self.report(bfind::Error::Io { path: entry.path().into(), source: e })).ok()
+                } else {
+                    None
+                };
+                match &mut self.format {
+                    Format::Tree(tree) => return tree.insert(entry),
+                    Format::Du(du) => return du.insert(&entry, metadata),
+                    Format::Dupes(dupes) => return dupes.insert(&entry, metadata),
+                    _ => {},
+                }
+                let path = entry.path();
+                let path = if self.strip_cwd_prefix {
+                    path.strip_prefix(".").or_else(|_| path.strip_prefix(self.cwd)).unwrap_or(path)
+                } else {
+                    path
+                };
+                let no_digests = Digests::default();
+                let digests = match self.hashes.as_ref().map(|hashes| hashes.digests(&entry)) {
+                    Some(Ok(digests)) => digests,
+                    Some(Err(e)) => {
+                        let source = io::Error::new(e.kind(), e.to_string());
+                        self.report(bfind::Error::Io { path: entry.path().into(), source });
+                        &no_digests
+                    },
+                    None => &no_digests,
+                };
+                if let Format::Exec(exec) = &self.format {
+                    let args = exec.args(&entry, path, metadata, digests);
+                    return self.exec(&args);
+                }
+                self.line.clear();
+                match &mut self.format {
+                    Format::Path => match &self.colors {
+                        Some(colors) => colors.paint(&mut self.line, &entry, path, metadata),
+                        None => write!(self.line, "{}", path.display()).unwrap(),
+                    },
+                    Format::Template(template) => template.render(&mut self.line, &entry, path, metadata, digests),
+                    Format::Json => write!(self.line, "{}", fields::json_record(&entry, path, metadata)).unwrap(),
+                    Format::Table(table) => table.render(&mut self.line, &entry, path, metadata, digests),
+                    Format::Long(long) => long.render(&mut self.line, &entry, path, metadata, self.colors.as_deref()),
+                    Format::Exec(_) | Format::Tree(_) | Format::Du(_) | Format::Dupes(_) => unreachable!(),
+                }
+                if let Err(e) = writeln!(self.out, "{}", self.line) {
+                    self.write_error(e);
+                }
+            },
+            Err(e @ bfind::Error::PathQueue { .. }) => self.error(e),
+            Err(e) => self.report(e),
+        }
+    }
+
+    /// Runs the command of `exec` and waits for it. A command that cannot be run or fails makes bfind exit with 1,
+    /// but the walk goes on.
+    fn exec(&mut self, args: &[OsString]) {
+        // The command writes to the same standard output.
+        self.flush();
+        match exec::run(args) {
+            Ok(true) => {},
+            Ok(false) => self.status = EXIT_FAILURE,
+            Err(e) => {
+                eprintln!("{}: {}: {}", self.prog, Path::new(&args[0]).display(), e);
+                self.status = EXIT_FAILURE;
+            },
+        }
+    }
+
+    /// Reports an error that does not change the exit status.
+    fn report(&mut self, e: bfind::Error) {
+        *self.errors.entry(stats::error_kind(&e)).or_default() += 1;
+        eprintln!("{}: {}", self.prog, e);
+    }
+
+    /// Prints what was collected for `--tree`, `du` and `dupes`.
+    fn finish(&mut self) {
+        let mut errors = Vec::new();
+        let rendered = match &mut self.format {
+            Format::Tree(tree) => tree.render(self.colors.as_deref()),
+            Format::Du(du) => du.render(),
+            Format::Dupes(dupes) => dupes.render(&mut errors),
+            _ => return,
+        };
+        for e in errors {
+            self.report(e);
+        }
+        if let Err(e) = self.out.write_all(rendered.as_bytes()) {
+            self.write_error(e);
+        }
+    }
+
+    /// Flushes the output, so that a checkpoint never counts entries that were not written yet.
+    fn flush(&mut self) {
+        if let Err(e) = self.out.flush() {
+            self.write_error(e);
+        }
+    }
+
+    fn write_error(&self, e: io::Error) -> ! {
+        if e.kind() == io::ErrorKind::BrokenPipe {
+            exit(EXIT_SUCCESS);
+        }
+        eprintln!("{}: {}", self.prog, e);
+        exit(EXIT_FAILURE);
+    }
+
+    fn error(&mut self, e: bfind::Error) {
+        *self.errors.entry(stats::error_kind(&e)).or_default() += 1;
+        eprintln!("{}: {}", self.prog, e);
+        self.status = EXIT_FAILURE;
+    }
 }
 
 #[derive(PartialEq, Eq)]
@@ -165,11 +558,6 @@ enum CliState {
     Expr,
 }
 
-fn print_help(prog: &str) {
-    println!("{}: [-H] [-L] [-d DEPTH] [-I IGNORE] [DIR ...] [VERB ...] [-- EXPR ...]", prog);
-    exit(0);
-}
-
 fn main() {
     let mut args: VecDeque<String> = env::args().collect();
 
@@ -179,59 +567,39 @@ fn main() {
     let mut verb = Verb::Print;
     let mut action_tokens = Vec::new();
     let mut expr_tokens: Vec<String> = Vec::new();
+    let mut print_config = false;
 
     let prog_path = args.pop_front().unwrap();
-    let prog = prog_path.rsplit('/').nth_back(0).unwrap();
+    let prog = prog_path.rsplit('/').next().unwrap();
     let cwd = env::current_dir().unwrap_or_else(|e| {
         eprintln!("{}: {}", prog, e);
-        exit(1);
+        exit(EXIT_FAILURE);
     });
 
+    let no_config = args.iter()
+        .take_while(|arg| *arg != "--" && cli::find_verb(arg).is_none())
+        .any(|arg| cli::find_option(arg).is_some_and(|spec| spec.opt == Opt::NoConfig));
+    if !no_config {
+        apply_defaults(prog, &mut opts);
+    }
+
     while let Some(arg) = args.pop_front() {
         match state {
             CliState::Options => {
-                if arg == "-H" {
-                    opts.allow_hidden = true;
-                } else if arg == "-L" {
-                    opts.follow_links = true;
-                } else if arg == "-h" || arg == "--help" {
-                    print_help(prog);
-                } else if arg == "-d" || arg == "--depth" {
-                    if let Some(depth_str) = args.pop_front() {
-                        if let Ok(depth) = depth_str.parse::<i32>() {
-                            if depth < 1 {
-                                eprintln!("{}: depth must be > 0", prog);
-                                exit(1);
-                            }
-                            opts.max_depth = depth;
-                        } else {
-                            eprintln!("{}: unable to parse \"{}\" as i32", prog, &depth_str);
-                            exit(1);
-                        }
-                    } else {
-                        eprintln!("{}: missing argument to -d", prog);
-                        exit(1);
+                let arg = split_inline_value(arg, &mut args);
+                if let Some(spec) = cli::find_option(&arg) {
+                    if spec.opt == Opt::PrintConfig {
+                        print_config = true;
                     }
-                } else if arg == "-I" || arg == "--ignore" {
-                    if let Some(ignore) = args.pop_front() {
-                        opts.ignores = ignore.split(',').map(|s| { s.to_string() }).collect();
-                    } else {
-                        eprintln!("{}: missing argument to -I", prog);
-                        exit(1);
-                    }
-                } else if arg == "--strip-cwd-prefix" {
-                    opts.strip_cwd_prefix = true;
-                } else if arg == "print" {
-                    verb = Verb::Print;
-                    state = CliState::Action;
-                } else if arg == "exec" {
-                    verb = Verb::Exec;
+                    apply_option(prog, spec, &mut args, &mut opts);
+                } else if let Some(spec) = cli::find_verb(&arg) {
+                    verb = spec.verb;
                     state = CliState::Action;
                 } else if arg == "--" {
                     state = CliState::Expr;
                 } else if arg.starts_with('-') {
                     eprintln!("{}: unrecognized argument: {}", prog, &arg);
-                    exit(1);
+                    exit(EXIT_USAGE);
                 } else {
                     roots.push(arg);
                 }
@@ -250,84 +618,231 @@ fn main() {
     }
 
     let num_threads = {
-        if let Ok(n) = thread::available_parallelism() {
+        if opts.threads > 0 {
+            opts.threads
+        } else if let Ok(n) = thread::available_parallelism() {
             n.get() + 1
         } else {
-            exit(1);
+            exit(EXIT_FAILURE);
         }
     };
 
-    let mut queues = Vec::new();
-    for _ in 0..num_threads {
-        let q = PathQueue::new((1024 * 512 / num_threads) as u32, (1024 * 512 / num_threads) as u32);
-        if let Ok(q) = q {
-            queues.push(q);
-        } else {
-            eprintln!("{}: {}", prog, q.unwrap_err());
-            exit(1);
-        }
+    if print_config {
+        print!("{}", config::effective(&opts, num_threads));
+        exit(EXIT_SUCCESS);
+    }
+
+    let du = verb == Verb::Du;
+    let dupes = verb == Verb::Dupes;
+    let exec = verb == Verb::Exec;
+    let print_format = verb == Verb::Print && !action_tokens.is_empty();
+    let formats = [opts.json && !dupes, opts.table.is_some(), opts.long, opts.tree, du, dupes, exec, print_format];
+    if formats.into_iter().filter(|&yes| yes).count() > 1 {
+        eprintln!("{}: only one of --json, --csv, --tsv, --long, --tree, du, dupes, exec and a print FORMAT may be given",
+                  prog);
+        exit(EXIT_USAGE);
+    }
+    if (opts.tree || du || dupes) && opts.checkpoint.is_some() {
+        eprintln!("{}: --tree, du and dupes print at the end, so they cannot be combined with --checkpoint", prog);
+        exit(EXIT_USAGE);
     }
+    let walk_roots = || {
+        let roots = if roots.is_empty() { vec![".".to_string()] } else { roots.clone() };
+        roots.into_iter().map(PathBuf::from)
+    };
+    let format = match action_tokens.as_slice() {
+        _ if du => {
+            let top = match action_tokens.as_slice() {
+                [] => du::DEFAULT_TOP,
+                [n] => n.parse().unwrap_or_else(|_| {
+                    eprintln!("{}: du takes a number of directories, got \"{}\"", prog, n);
+                    exit(EXIT_USAGE);
+                }),
+                _ => {
+                    eprintln!("{}: du takes a single N argument", prog);
+                    exit(EXIT_USAGE);
+                },
+            };
+            Format::Du(Du::new(walk_roots(), top))
+        },
+        [] if dupes => Format::Dupes(Dupes::new(num_threads, opts.json)),
+        [_, ..] if dupes => {
+            eprintln!("{}: dupes takes no arguments", prog);
+            exit(EXIT_USAGE);
+        },
+        [] if exec => {
+            eprintln!("{}: exec takes a COMMAND", prog);
+            exit(EXIT_USAGE);
+        },
+        _ if exec => Format::Exec(Exec::new(action_tokens.iter().map(|arg| Template::parse(arg).unwrap_or_else(|e| {
+            eprintln!("{}: {}: {}", prog, arg, e);
+            exit(EXIT_USAGE);
+        })).collect())),
+        [_, _, ..] => {
+            eprintln!("{}: print takes a single FORMAT argument", prog);
+            exit(EXIT_USAGE);
+        },
+        [format] => Format::Template(Template::parse(format).unwrap_or_else(|e| {
+            eprintln!("{}: {}", prog, e);
+            exit(EXIT_USAGE);
+        })),
+        [] if opts.json => Format::Json,
+        [] if opts.long => Format::Long(Long::default()),
+        [] if opts.tree => Format::Tree(Tree::new(walk_roots(), opts.tree_totals)),
+        [] => match opts.table {
+            Some(dialect) => Format::Table(Table::new(dialect, opts.columns.clone())),
+            None => Format::Path,
+        },
+    };
 
-    let mut counter: usize = 0;
+    // Only the paths of the default, long and tree formats are colored.
+    let colors = match format {
+        Format::Path | Format::Long(_) | Format::Tree(_) if opts.color.enabled() => Some(Arc::new(Colors::from_env())),
+        _ => None,
+    };
 
-    if roots.is_empty() {
-        if let Err(e) = queues[0].push(PathBuf::from(".")) {
+    let mut builder = WalkBuilder::new(roots.first().map_or(".", String::as_str))
+        .hidden(opts.allow_hidden)
+        .follow_links(opts.follow_links)
+        .max_depth(opts.max_depth as usize)
+        .same_file_system(opts.one_file_system)
+        .ignore(&opts.ignores)
+        .threads(num_threads)
+        .prefetch_metadata(format.needs_metadata() || colors.is_some())
+        .spill(!opts.no_spill)
+        .spill_compression(opts.spill_compression);
+    if let Some(dir) = &opts.spill_dir {
+        builder = builder.spill_dir(dir);
+    }
+    if let Some(size) = opts.max_spill {
+        builder = builder.max_spill(size);
+    }
+    if let Some(size) = opts.memory {
+        builder = builder.memory_budget(MemoryBudget::new(size));
+    }
+    for root in roots.iter().skip(1) {
+        builder = builder.add_root(root);
+    }
+    if let Some(path) = &opts.resume {
+        builder = builder.resume(path);
+    }
+    let matcher = (!expr_tokens.is_empty()).then(|| {
+        let content = expr::ContentOptions { binary: opts.binary, max_size: opts.max_content_size };
+        Matcher::parse(&expr_tokens, content).unwrap_or_else(|e| {
             eprintln!("{}: {}", prog, e);
-            exit(1);
+            exit(EXIT_USAGE);
+        })
+    });
+    // The expression and the output share the digests, so that each file is read once for all the algorithms.
+    let mut algorithms = format.algorithms();
+    let hashes = (!algorithms.is_empty()).then(|| {
+        for algorithm in matcher.iter().flat_map(Matcher::algorithms) {
+            if !algorithms.contains(&algorithm) {
+                algorithms.push(algorithm);
+            }
+        }
+        Arc::new(Hashes::new(algorithms))
+    });
+    // Likewise the names of users and groups, for `nouser`, `nogroup` and the long listing.
+    let users = Arc::new(Mutex::new(Users::default()));
+    if let Some(mut matcher) = matcher {
+        if let Some(hashes) = &hashes {
+            matcher.share_hashes(Arc::clone(hashes));
         }
-        counter = 1;
-    } else {
-        let dotdir = Path::new(".");
-        let dotdotdir = Path::new("..");
-        let rootdir = Path::new("/");
-        for root in &roots {
-            let path = PathBuf::from(root);
-            if !opts.follow_links && path.is_symlink() {
-                continue;
+        matcher.share_users(Arc::clone(&users));
+        builder = builder.filter(move |entry| matcher.matches(entry));
+    }
+    // Runs last, so that only the entries that will be printed are hashed.
+    if let Some(hashes) = &hashes {
+        let hashes = Arc::clone(hashes);
+        builder = builder.filter(move |entry| {
+            let _ = hashes.digests(entry);
+            true
+        });
+    }
+    if format.needs_link_target() {
+        builder = builder.filter(|entry| {
+            fields::link_target(entry);
+            true
+        });
+    }
+    // Symbolic links are colored by their targets, which are stat'ed in the worker threads too.
+    if let Some(colors) = &colors {
+        let colors = Arc::clone(colors);
+        builder = builder.filter(move |entry| {
+            if entry.is_symlink() {
+                colors.prefetch(entry);
             }
-            if path != dotdir && path != dotdotdir && path != rootdir {
-                eprintln!("{}", path.display());
-                if let Some(file_name) = path.file_name() {
-                    if let Some(file_name) = file_name.to_str() {
-                        if !opts.allow_hidden {
-                            if let Some(first_char) = file_name.chars().next() {
-                                if first_char == '.' {
-                                    continue;
-                                }
-                            }
-                        }
-                        if opts.ignores.iter().any(|item| item == file_name) {
-                            continue;
-                        }
-                    } else {
-                        eprintln!("{}: {}: cannot read filename", prog, path.display())
-                    }
-                } else {
-                    unreachable!("path ends with \"..\", which should not happen");
+            true
+        });
+    }
+    if let Format::Long(_) = format {
+        builder = builder.filter(move |entry| {
+            long::prefetch(entry, &users);
+            true
+        });
+    }
+    let start = Instant::now();
+    let mut walker = builder.build().unwrap_or_else(|e| {
+        eprintln!("{}: {}", prog, e);
+        exit(EXIT_FAILURE);
+    });
+
+    let mut printer = Printer {
+        prog,
+        out: Output { stdout: io::stdout().lock(), held: opts.checkpoint.is_some().then(Vec::new) },
+        format,
+        colors,
+        line: String::new(),
+        strip_cwd_prefix: opts.strip_cwd_prefix,
+        cwd: &cwd,
+        status: EXIT_SUCCESS,
+        errors: BTreeMap::new(),
+        hashes,
+    };
+    if let Format::Table(table) = &printer.format {
+        if let Err(e) = writeln!(printer.out, "{}", table.header()) {
+            printer.write_error(e);
+        }
+    }
+    let reporter = opts.progress.then(|| progress::Reporter::start(prog, walker.monitor()));
+    let mut last_checkpoint = Instant::now();
+    while let Some(entry) = walker.next() {
+        printer.print(entry);
+        if let Some(path) = &opts.checkpoint {
+            if last_checkpoint.elapsed() >= opts.checkpoint_interval || printer.out.held_len() >= HELD_OUTPUT_LIMIT {
+                let paused = walker.pause(|entry| printer.print(entry));
+                printer.flush();
+                if let Err(e) = paused.checkpoint(path) {
+                    printer.error(e);
                 }
-            }
-            if let Err(e) = queues[0].push(path) {
-                eprintln!("{}: {}", prog, e);
-                exit(1)
-            } else {
-                counter += 1;
+                last_checkpoint = Instant::now();
             }
         }
     }
-
-    let counter = AtomicUsize::new(counter);
-    if let Err(e) = thread::scope(|s| -> Result<()> {
-        let cwd = &cwd;
-        let opts = &opts;
-        let queues = &queues;
-        let counter = &counter;
-        for i in 0..num_threads {
-            s.spawn(move|| -> Result<()> {
-                breadth_first_traverse(prog, cwd, opts, queues, i, counter)
-            });
+    if let Some(path) = &opts.checkpoint {
+        let paused = walker.pause(|entry| printer.print(entry));
+        printer.flush();
+        if let Err(e) = paused.checkpoint(path) {
+            printer.error(e);
+        }
+    }
+    printer.finish();
+    printer.flush();
+    drop(reporter);
+    if opts.stats || opts.stats_json {
+        let summary = Summary {
+            walk: walker.monitor().stats(),
+            errors: printer.errors,
+            wall: start.elapsed(),
+            cpu: stats::cpu_time(),
+        };
+        if opts.stats {
+            eprint!("{}", summary.human());
+        }
+        if opts.stats_json {
+            eprintln!("{}", summary.json());
         }
-        Ok(())
-    }) {
-        eprintln!("{}: {}", prog, e);
     }
+    exit(printer.status);
 }
@@ -1,23 +1,20 @@
-use std::alloc::alloc;
-use std::alloc::dealloc;
-use std::alloc::Layout;
-use std::cell::UnsafeCell;
+use std::collections::VecDeque;
 use std::ffi::OsStr;
 use std::fs::File;
+use std::fs;
 use std::io;
-use std::io::BufRead;
-use std::io::BufReader;
-use std::io::BufWriter;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::io::Write;
-use std::mem::align_of;
 use std::mem::size_of;
-use std::num::Wrapping;
-use std::path::Path;
 use std::path::PathBuf;
-use std::sync::atomic::AtomicBool;
-use std::sync::atomic::AtomicU32;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use std::sync::PoisonError;
 use std::sync::atomic::Ordering;
+
+use crate::sync::Mutex;
 use tempfile::NamedTempFile;
 use thiserror::Error;
 
@@ -32,12 +29,225 @@ pub enum Error {
         source: io::Error
     },
 
-    #[error("path_queue::SpinLockFailed")]
-    SpinLockFailed,
+    #[error("path_queue::OutOfMemory: unable to allocate a buffer of {capacity} entries")]
+    OutOfMemory {
+        capacity: usize
+    },
+
+    #[error("path_queue::CapacityOverflow: a buffer of {capacity} entries is larger than the address space")]
+    CapacityOverflow {
+        capacity: usize
+    },
+
+    #[error("path_queue::SpillLimitExceeded: spill files would exceed {limit} bytes")]
+    SpillLimitExceeded {
+        limit: u64
+    },
+
+    #[error("path_queue::CorruptSpillFile")]
+    CorruptSpillFile,
+
+    #[error("path_queue::CompressionUnavailable: {0:?} support is not compiled in")]
+    CompressionUnavailable(Compression),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// The type of a queued entry, stored as a single byte when spilled.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(u8)]
+pub enum FileKind {
+    Unknown,
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+impl From<fs::FileType> for FileKind {
+    fn from(t: fs::FileType) -> Self {
+        if t.is_dir() {
+            FileKind::Dir
+        } else if t.is_file() {
+            FileKind::File
+        } else if t.is_symlink() {
+            FileKind::Symlink
+        } else {
+            FileKind::Other
+        }
+    }
+}
+
+impl FileKind {
+    fn from_u8(b: u8) -> Self {
+        match b {
+            1 => FileKind::File,
+            2 => FileKind::Dir,
+            3 => FileKind::Symlink,
+            4 => FileKind::Other,
+            _ => FileKind::Unknown,
+        }
+    }
+}
+
+/// A directory waiting in a [`PathQueue`], with what the traversal knows about it so that it need not be stat'ed
+/// again when it is popped.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct QueueEntry {
+    pub path:       PathBuf,
+
+    /// Number of levels below the starting directory. Starting directories have depth 0.
+    pub depth:      u32,
+
+    /// Index of the starting directory the entry was found in.
+    pub root:       u32,
+
+    pub dev:        u64,
+    pub ino:        u64,
+    pub kind:       FileKind,
+}
+
+impl QueueEntry {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, depth: 0, root: 0, dev: 0, ino: 0, kind: FileKind::Unknown }
+    }
+
+    /// Appends the entry to a spill block. The path is stored as the length of the prefix it shares with the
+    /// previous path in the block followed by the rest, since siblings share their whole parent path.
+    fn encode(&self, prev_path: &[u8], buf: &mut Vec<u8>) {
+        let path = self.path.as_os_str().as_encoded_bytes();
+        let shared = path.iter().zip(prev_path).take_while(|(a, b)| a == b).count();
+        write_varint(buf, shared as u64);
+        write_varint(buf, (path.len() - shared) as u64);
+        buf.extend_from_slice(&path[shared..]);
+        write_varint(buf, self.depth as u64);
+        write_varint(buf, self.root as u64);
+        write_varint(buf, self.dev);
+        write_varint(buf, self.ino);
+        buf.push(self.kind as u8);
+    }
+
+    /// Decodes the entry at `*pos` of a spill block, where `prev_path` holds the previous path in the block and is
+    /// updated to this one.
+    fn decode(buf: &[u8], pos: &mut usize, prev_path: &mut Vec<u8>) -> Result<Self> {
+        let shared = read_varint(buf, pos)? as usize;
+        let suffix_len = read_varint(buf, pos)? as usize;
+        if shared > prev_path.len() || buf.len() - *pos < suffix_len {
+            return Err(Error::CorruptSpillFile);
+        }
+        prev_path.truncate(shared);
+        prev_path.extend_from_slice(&buf[*pos..*pos + suffix_len]);
+        *pos += suffix_len;
+        let depth = read_varint(buf, pos)? as u32;
+        let root = read_varint(buf, pos)? as u32;
+        let dev = read_varint(buf, pos)?;
+        let ino = read_varint(buf, pos)?;
+        let kind = FileKind::from_u8(*buf.get(*pos).ok_or(Error::CorruptSpillFile)?);
+        *pos += 1;
+        let path = path_from_bytes(prev_path)?;
+        Ok(Self { path, depth, root, dev, ino, kind })
+    }
+}
+
+/// Converts the bytes of a path written by [`QueueEntry::encode`] back to a path. They may come from a checkpoint
+/// written by another process, so they are validated where not all bytes make a valid path.
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(PathBuf::from(OsStr::from_bytes(bytes)))
+    }
+    #[cfg(not(unix))]
+    {
+        std::str::from_utf8(bytes).map(PathBuf::from).map_err(|_| Error::CorruptSpillFile)
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = *buf.get(*pos).ok_or(Error::CorruptSpillFile)?;
+        *pos += 1;
+        n |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(Error::CorruptSpillFile)
+}
+
+/// How spill blocks are compressed, on top of the prefix compression of their paths.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(u8)]
+pub enum Compression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Default for Compression {
+    /// LZ4 if it is compiled in, otherwise none.
+    fn default() -> Self {
+        if cfg!(feature = "lz4") {
+            Compression::Lz4
+        } else {
+            Compression::None
+        }
+    }
+}
+
+impl Compression {
+    pub fn is_available(self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Lz4 => cfg!(feature = "lz4"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    fn from_u8(b: u8) -> Result<Self> {
+        match b {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Lz4),
+            2 => Ok(Compression::Zstd),
+            _ => Err(Error::CorruptSpillFile),
+        }
+    }
+
+    fn compress(self, raw: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(raw.to_vec()),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::block::compress(raw)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(zstd::bulk::compress(raw, 1)?),
+            #[allow(unreachable_patterns)]
+            _ => Err(Error::CompressionUnavailable(self)),
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    fn decompress(self, data: &[u8], raw_len: usize) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => lz4_flex::block::decompress(data, raw_len).map_err(|_| Error::CorruptSpillFile),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::decompress(data, raw_len).map_err(|_| Error::CorruptSpillFile),
+            #[allow(unreachable_patterns)]
+            _ => Err(Error::CompressionUnavailable(self)),
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum PathQueueState {
     Empty,
@@ -62,64 +272,47 @@ impl PathQueueState {
     }
 }
 
+/// A ring buffer of a fixed capacity.
 #[derive(Debug)]
 struct MemPathQueue {
-    capacity:       u32,
-    pop_count:      AtomicU32,
-    push_count:     AtomicU32,
-    buf:            *mut PathBuf,
+    capacity:       usize,
+    buf:            VecDeque<QueueEntry>,
 }
 
 impl MemPathQueue {
-    pub fn new(capacity: u32) -> Self {
-        let capacity = capacity.next_power_of_two();
-        let buf = unsafe {
-            let layout = Layout::from_size_align(size_of::<PathBuf>() * capacity as usize, align_of::<PathBuf>()).expect("Bad layout");
-            alloc(layout) as *mut PathBuf
-        };
-        Self {
-            capacity,
-            pop_count: AtomicU32::new(0),
-            push_count: AtomicU32::new(0),
-            buf
-        }
+    pub fn new(capacity: u32) -> Result<Self> {
+        let capacity = capacity.checked_next_power_of_two()
+            .ok_or(Error::CapacityOverflow { capacity: capacity as usize })? as usize;
+        Self::with_capacity(capacity)
     }
 
-    // safe if and only if there is only one push thread
-    pub fn push(&mut self, path: PathBuf) -> Option<PathBuf> {
-        let push_count = self.push_count.load(Ordering::Acquire);
-        let pop_count = self.pop_count.load(Ordering::Acquire);
-        if push_count - pop_count == self.capacity {
-            return Some(path);
+    fn with_capacity(capacity: usize) -> Result<Self> {
+        // Checked here, as `try_reserve_exact` does not tell a size that cannot be represented from a failed allocation.
+        if capacity.checked_mul(size_of::<QueueEntry>()).is_none_or(|size| size > isize::MAX as usize) {
+            return Err(Error::CapacityOverflow { capacity });
         }
-        unsafe {
-            self.buf.add((push_count & (self.capacity - 1)) as usize).write(path);
+        let mut buf = VecDeque::new();
+        buf.try_reserve_exact(capacity).map_err(|_| Error::OutOfMemory { capacity })?;
+        Ok(Self { capacity, buf })
+    }
+
+    pub fn push(&mut self, entry: QueueEntry) -> Option<QueueEntry> {
+        if self.buf.len() == self.capacity {
+            return Some(entry);
         }
-        self.push_count.fetch_add(1, Ordering::Release);
+        self.buf.push_back(entry);
         None
     }
 
-    // safe if and only if there is only one pop thread
-    pub fn pop(&mut self) -> Option<PathBuf> {
-        let push_count = self.push_count.load(Ordering::Acquire);
-        let pop_count = self.pop_count.load(Ordering::Acquire);
-        if push_count - pop_count == 0 {
-            return None;
-        }
-        let path = unsafe {
-            self.buf.add((pop_count & (self.capacity - 1)) as usize).read()
-        };
-        self.pop_count.fetch_add(1, Ordering::Release);
-        Some(path)
+    pub fn pop(&mut self) -> Option<QueueEntry> {
+        self.buf.pop_front()
     }
 
     #[allow(dead_code)]
     pub fn state(&self) -> PathQueueState {
-        let push_count = self.push_count.load(Ordering::Acquire);
-        let pop_count = self.pop_count.load(Ordering::Acquire);
-        if push_count - pop_count == 0 {
+        if self.buf.is_empty() {
             PathQueueState::Empty
-        } else if push_count - pop_count == self.capacity {
+        } else if self.buf.len() == self.capacity {
             PathQueueState::Full
         } else {
             PathQueueState::PartiallyFilled
@@ -127,279 +320,679 @@ impl MemPathQueue {
     }
 }
 
-impl Drop for MemPathQueue {
-    fn drop(&mut self) {
-        unsafe {
-            let layout = Layout::from_size_align(size_of::<PathBuf>() * self.capacity as usize, align_of::<PathBuf>()).expect("Bad layout");
-            dealloc(self.buf as *mut u8, layout);
+/// A limit on the memory used by the entries of a set of queues, shared by clones of the budget.
+///
+/// The ring buffers of a queue are charged when it is created, and the heap allocations of the paths in them while
+/// they are queued. A queue spills to disk instead of growing beyond the budget, so usage can only exceed the limit
+/// if the ring buffers alone do.
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    inner: Arc<MemoryBudgetInner>,
+}
+
+#[derive(Debug)]
+struct MemoryBudgetInner {
+    limit:  u64,
+    used:   AtomicU64,
+    peak:   AtomicU64,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            inner: Arc::new(MemoryBudgetInner {
+                limit,
+                used: AtomicU64::new(0),
+                peak: AtomicU64::new(0),
+            })
+        }
+    }
+
+    /// A budget without a limit, which only tracks usage.
+    pub fn unlimited() -> Self {
+        Self::new(u64::MAX)
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.inner.limit
+    }
+
+    pub fn used(&self) -> u64 {
+        self.inner.used.load(Ordering::Relaxed)
+    }
+
+    /// The highest usage so far.
+    pub fn peak(&self) -> u64 {
+        self.inner.peak.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn try_reserve(&self, bytes: u64) -> bool {
+        let reserved = self.inner.used.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            used.checked_add(bytes).filter(|&used| used <= self.inner.limit)
+        });
+        if let Ok(used) = reserved {
+            self.inner.peak.fetch_max(used + bytes, Ordering::Relaxed);
         }
+        reserved.is_ok()
     }
+
+    pub(crate) fn reserve(&self, bytes: u64) {
+        let used = self.inner.used.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.inner.peak.fetch_max(used, Ordering::Relaxed);
+    }
+
+    pub(crate) fn release(&self, bytes: u64) {
+        self.inner.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+/// Heap memory owned by a queued entry, charged to the [`MemoryBudget`] while it is in a ring buffer.
+pub(crate) fn heap_size(entry: &QueueEntry) -> u64 {
+    entry.path.capacity() as u64
 }
 
-unsafe impl Sync for MemPathQueue {}
+/// How a [`PathQueue`] spills to disk when its ring buffers are full or its memory budget is used up.
+///
+/// Queues created with clones of the same options share the limit on the total size of their spill files, and the
+/// memory budget.
+#[derive(Debug, Clone)]
+pub struct SpillOptions {
+    /// Keep everything in memory instead of spilling to disk.
+    pub disabled:       bool,
+
+    /// Directory for spill files, instead of the default temporary directory.
+    pub dir:            Option<PathBuf>,
+
+    /// Maximum total size of the spill files in bytes.
+    pub max_size:       Option<u64>,
+
+    pub compression:    Compression,
+
+    /// Memory the queues may use before spilling. It is not enforced if spilling is disabled.
+    pub memory:         MemoryBudget,
+
+    written:            Arc<AtomicU64>,
+    read:               Arc<AtomicU64>,
+    spilled:            Arc<AtomicU64>,
+}
 
+impl Default for SpillOptions {
+    fn default() -> Self {
+        Self {
+            disabled: false,
+            dir: None,
+            max_size: None,
+            compression: Compression::default(),
+            memory: MemoryBudget::default(),
+            written: Arc::new(AtomicU64::new(0)),
+            read: Arc::new(AtomicU64::new(0)),
+            spilled: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl SpillOptions {
+    /// Total number of bytes written to spill files by queues sharing these options.
+    pub fn bytes_written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    /// Total number of bytes read back from spill files by queues sharing these options.
+    pub fn bytes_read(&self) -> u64 {
+        self.read.load(Ordering::Relaxed)
+    }
+
+    /// Number of entries currently queued in spill files by queues sharing these options.
+    pub fn entries_spilled(&self) -> u64 {
+        self.spilled.load(Ordering::Relaxed)
+    }
+}
+
+/// Size of the uncompressed contents of a spill block, above which it is written to the file.
+const SPILL_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Header of a spill block: number of entries, uncompressed and stored length, and compression.
+const SPILL_BLOCK_HEADER_LEN: usize = 4 + 4 + 4 + 1;
+
+/// Longest block a reader accepts, compressed or not. Blocks are written once they reach [`SPILL_BLOCK_SIZE`], so they
+/// exceed it by less than one entry, whose path is far shorter than the rest of this bound.
+const MAX_SPILL_BLOCK_LEN: usize = SPILL_BLOCK_SIZE + (16 << 20);
+
+/// A FIFO queue in a temporary file. Entries are collected into blocks of prefix-compressed paths, which are
+/// optionally compressed further and written with a single write each. The block being filled is popped from
+/// memory directly once the file has been read up.
 #[derive(Debug)]
 struct TempfilePathQueue {
-    pop_count:      AtomicUsize,
-    push_count:     AtomicUsize,
-    writer:         UnsafeCell<BufWriter<File>>,
-    reader:         UnsafeCell<BufReader<File>>,
+    options:        SpillOptions,
+    writer:         File,
+    reader:         File,
+    len:            usize,
+
+    write_block:    Vec<u8>,
+    write_count:    u32,
+    write_prev:     Vec<u8>,
+    blocks_written: usize,
+
+    read_block:     Vec<u8>,
+    read_pos:       usize,
+    read_count:     u32,
+    read_prev:      Vec<u8>,
+    blocks_read:    usize,
 }
 
 impl TempfilePathQueue {
-    pub fn new() -> Result<Self> {
-        let f = NamedTempFile::new()?;
-        let q = Self {
-            pop_count: AtomicUsize::new(0),
-            push_count: AtomicUsize::new(0),
-            writer: UnsafeCell::new(BufWriter::new(f.reopen()?)),
-            reader: UnsafeCell::new(BufReader::new(f.reopen()?)),
+    pub fn new(options: SpillOptions) -> Result<Self> {
+        if !options.compression.is_available() {
+            return Err(Error::CompressionUnavailable(options.compression));
+        }
+        // Separate descriptors, so that the reading and writing offsets are independent.
+        let f = match &options.dir {
+            Some(dir) => NamedTempFile::new_in(dir)?,
+            None => NamedTempFile::new()?,
         };
-        drop(f);
-        Ok(q)
+        Ok(Self {
+            options,
+            writer: f.reopen()?,
+            reader: f.reopen()?,
+            len: 0,
+            write_block: Vec::new(),
+            write_count: 0,
+            write_prev: Vec::new(),
+            blocks_written: 0,
+            read_block: Vec::new(),
+            read_pos: 0,
+            read_count: 0,
+            read_prev: Vec::new(),
+            blocks_read: 0,
+        })
     }
 
-    // safe if and only if there is only one push thread
-    pub fn push(&mut self, path: &Path) -> Result<()> {
-        let writer = unsafe { &mut *self.writer.get() };
-        writer.write_all(path.as_os_str().as_encoded_bytes())?;
-        writer.write_all(b"\0")?;
-        writer.flush()?;
-        self.push_count.fetch_add(1, Ordering::Release);
+    pub fn push(&mut self, entry: &QueueEntry) -> Result<()> {
+        entry.encode(&self.write_prev, &mut self.write_block);
+        self.write_prev.clear();
+        self.write_prev.extend_from_slice(entry.path.as_os_str().as_encoded_bytes());
+        self.write_count += 1;
+        self.len += 1;
+        self.options.spilled.fetch_add(1, Ordering::Relaxed);
+        if self.write_block.len() >= SPILL_BLOCK_SIZE {
+            self.write_out()?;
+        }
         Ok(())
     }
 
-    // safe if and only if there is only one pop thread
-    pub fn pop(&mut self) -> Result<Option<PathBuf>> {
-        let reader = unsafe { &mut *self.reader.get() };
-        let push_count = self.push_count.load(Ordering::Acquire);
-        let pop_count = self.pop_count.load(Ordering::Acquire);
-        if push_count - pop_count == 0 {
+    pub fn pop(&mut self) -> Result<Option<QueueEntry>> {
+        if self.len == 0 {
             return Ok(None);
         }
-        let mut buffer = vec![];
-        reader.read_until(b'\0', &mut buffer)?;
-        let delim = buffer.pop();
-        assert_eq!(delim, Some(b'\0'));
-        self.pop_count.fetch_add(1, Ordering::Release);
-        let path = PathBuf::from(unsafe { OsStr::from_encoded_bytes_unchecked(&buffer) });
-        Ok(Some(path))
+        if self.read_count == 0 {
+            if self.blocks_read < self.blocks_written {
+                self.read_in()?;
+            } else {
+                self.read_block = std::mem::take(&mut self.write_block);
+                self.read_count = self.write_count;
+                self.write_count = 0;
+                self.write_prev.clear();
+            }
+            self.read_pos = 0;
+            self.read_prev.clear();
+        }
+        let entry = QueueEntry::decode(&self.read_block, &mut self.read_pos, &mut self.read_prev)?;
+        self.read_count -= 1;
+        self.len -= 1;
+        self.options.spilled.fetch_sub(1, Ordering::Relaxed);
+        Ok(Some(entry))
+    }
+
+    /// Writes the queued entries to `writer` in order, leaving them queued. The blocks in the file are read again
+    /// without being counted as read.
+    fn write_to<W: Write>(&mut self, writer: &mut EntryWriter<W>) -> Result<()> {
+        push_decoded(writer, &self.read_block, self.read_pos, self.read_prev.clone(), self.read_count)?;
+        let offset = self.reader.stream_position()?;
+        let result = (self.blocks_read..self.blocks_written).try_for_each(|_| {
+            let (block, count, _) = read_block(&mut self.reader)?;
+            push_decoded(writer, &block, 0, Vec::new(), count)
+        });
+        self.reader.seek(SeekFrom::Start(offset))?;
+        result?;
+        push_decoded(writer, &self.write_block, 0, Vec::new(), self.write_count)
+    }
+
+    fn write_out(&mut self) -> Result<()> {
+        let data = self.options.compression.compress(&self.write_block)?;
+        let block_len = (SPILL_BLOCK_HEADER_LEN + data.len()) as u64;
+        let written = self.options.written.fetch_add(block_len, Ordering::Relaxed) + block_len;
+        if let Some(limit) = self.options.max_size {
+            if written > limit {
+                self.options.written.fetch_sub(block_len, Ordering::Relaxed);
+                return Err(Error::SpillLimitExceeded { limit });
+            }
+        }
+        let block = frame_block(self.write_count, self.write_block.len(), self.options.compression, &data);
+        self.writer.write_all(&block)?;
+        self.write_block.clear();
+        self.write_count = 0;
+        self.write_prev.clear();
+        self.blocks_written += 1;
+        Ok(())
+    }
+
+    fn read_in(&mut self) -> Result<()> {
+        let block_len;
+        (self.read_block, self.read_count, block_len) = read_block(&mut self.reader)?;
+        self.options.read.fetch_add(block_len as u64, Ordering::Relaxed);
+        self.blocks_read += 1;
+        Ok(())
     }
 
     pub fn state(&self) -> PathQueueState {
-        let push_count = self.push_count.load(Ordering::Acquire);
-        let pop_count = self.pop_count.load(Ordering::Acquire);
-        if push_count - pop_count == 0 {
-            return PathQueueState::Empty;
+        if self.len == 0 {
+            PathQueueState::Empty
+        } else {
+            PathQueueState::PartiallyFilled
         }
-        PathQueueState::PartiallyFilled
     }
 }
 
-unsafe impl Sync for TempfilePathQueue {}
+impl Drop for TempfilePathQueue {
+    fn drop(&mut self) {
+        self.options.spilled.fetch_sub(self.len as u64, Ordering::Relaxed);
+    }
+}
 
-#[derive(Debug)]
-struct SpinLock {
-    locked: AtomicBool
+/// Prepends the header to a block of `count` entries, `raw_len` bytes before compression.
+fn frame_block(count: u32, raw_len: usize, compression: Compression, data: &[u8]) -> Vec<u8> {
+    let mut block = Vec::with_capacity(SPILL_BLOCK_HEADER_LEN + data.len());
+    block.extend_from_slice(&count.to_le_bytes());
+    block.extend_from_slice(&(raw_len as u32).to_le_bytes());
+    block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    block.push(compression as u8);
+    block.extend_from_slice(data);
+    block
 }
 
-impl SpinLock {
-    pub fn new() -> Self {
-        SpinLock { locked: AtomicBool::new(false) }
+/// Reads a block written by [`frame_block`], returning the decompressed entries, their count and the length of the
+/// block in the file.
+fn read_block<R: Read>(reader: &mut R) -> Result<(Vec<u8>, u32, usize)> {
+    let mut header = [0u8; SPILL_BLOCK_HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    let u32_at = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+    let (raw_len, len) = (u32_at(4) as usize, u32_at(8) as usize);
+    if raw_len > MAX_SPILL_BLOCK_LEN || len > MAX_SPILL_BLOCK_LEN {
+        return Err(Error::CorruptSpillFile);
     }
+    let mut data = vec![0u8; len];
+    reader.read_exact(&mut data)?;
+    let block = Compression::from_u8(header[12])?.decompress(&data, raw_len)?;
+    Ok((block, u32_at(0), SPILL_BLOCK_HEADER_LEN + data.len()))
+}
 
-    pub fn try_lock(&self) -> Result<SpinLockGuard> {
-        if let Ok(_) = self.locked.compare_exchange_weak(false, true, Ordering::Release, Ordering::Acquire) {
-            Ok(SpinLockGuard { lock: self })
-        } else {
-            Err(Error::SpinLockFailed)
+/// Decodes `count` entries of a block starting at `pos`, after the path `prev`, and pushes them to `writer`.
+fn push_decoded<W: Write>(writer: &mut EntryWriter<W>, block: &[u8], mut pos: usize, mut prev: Vec<u8>,
+                          count: u32) -> Result<()> {
+    for _ in 0..count {
+        writer.push(&QueueEntry::decode(block, &mut pos, &mut prev)?)?;
+    }
+    Ok(())
+}
+
+/// Writes entries in the format of spill files to a file that outlives the queue, such as a checkpoint.
+///
+/// The entries end with an empty block, so they can be followed by other data.
+pub struct EntryWriter<W: Write> {
+    inner:          W,
+    compression:    Compression,
+    block:          Vec<u8>,
+    count:          u32,
+    prev:           Vec<u8>,
+}
+
+impl<W: Write> EntryWriter<W> {
+    pub fn new(inner: W, compression: Compression) -> Result<Self> {
+        if !compression.is_available() {
+            return Err(Error::CompressionUnavailable(compression));
         }
+        Ok(Self { inner, compression, block: Vec::new(), count: 0, prev: Vec::new() })
     }
 
-    pub fn unlock(&self) {
-        self.locked.store(false, Ordering::Release);
+    pub fn push(&mut self, entry: &QueueEntry) -> Result<()> {
+        entry.encode(&self.prev, &mut self.block);
+        self.prev.clear();
+        self.prev.extend_from_slice(entry.path.as_os_str().as_encoded_bytes());
+        self.count += 1;
+        if self.block.len() >= SPILL_BLOCK_SIZE {
+            self.write_out()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the last block and the end marker, and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        if self.count > 0 {
+            self.write_out()?;
+        }
+        self.write_out()?;
+        Ok(self.inner)
+    }
+
+    fn write_out(&mut self) -> Result<()> {
+        let data = self.compression.compress(&self.block)?;
+        self.inner.write_all(&frame_block(self.count, self.block.len(), self.compression, &data))?;
+        self.block.clear();
+        self.count = 0;
+        self.prev.clear();
+        Ok(())
     }
 }
 
-struct SpinLockGuard<'a> {
-    pub lock: &'a SpinLock
+/// Reads the entries written by an [`EntryWriter`], up to the end marker.
+pub struct EntryReader<R: Read> {
+    inner:          R,
+    block:          Vec<u8>,
+    pos:            usize,
+    count:          u32,
+    prev:           Vec<u8>,
+    done:           bool,
 }
 
-impl<'a> Drop for SpinLockGuard<'a> {
-    fn drop(&mut self) {
-        self.lock.unlock();
+impl<R: Read> EntryReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, block: Vec::new(), pos: 0, count: 0, prev: Vec::new(), done: false }
+    }
+
+    /// Returns the underlying reader, positioned after the end marker if all entries were read.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn next_entry(&mut self) -> Result<Option<QueueEntry>> {
+        if self.count == 0 {
+            if self.done {
+                return Ok(None);
+            }
+            (self.block, self.count, _) = read_block(&mut self.inner)?;
+            self.pos = 0;
+            self.prev.clear();
+            if self.count == 0 {
+                self.done = true;
+                return Ok(None);
+            }
+        }
+        self.count -= 1;
+        QueueEntry::decode(&self.block, &mut self.pos, &mut self.prev).map(Some)
+    }
+}
+
+impl<R: Read> Iterator for EntryReader<R> {
+    type Item = Result<QueueEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
     }
 }
 
+/// Where entries go when both ring buffers of a [`PathQueue`] are full.
+#[derive(Debug)]
+enum Overflow {
+    Tempfile(TempfilePathQueue),
+    Mem(VecDeque<QueueEntry>),
+}
+
+impl Overflow {
+    fn new(options: &SpillOptions) -> Result<Self> {
+        if options.disabled {
+            Ok(Overflow::Mem(VecDeque::new()))
+        } else {
+            Ok(Overflow::Tempfile(TempfilePathQueue::new(options.clone())?))
+        }
+    }
+
+    fn push(&mut self, entry: QueueEntry) -> Result<()> {
+        match self {
+            Overflow::Tempfile(q) => q.push(&entry),
+            Overflow::Mem(q) => {
+                q.push_back(entry);
+                Ok(())
+            },
+        }
+    }
+
+    fn pop(&mut self) -> Result<Option<QueueEntry>> {
+        match self {
+            Overflow::Tempfile(q) => q.pop(),
+            Overflow::Mem(q) => Ok(q.pop_front()),
+        }
+    }
+
+    fn write_to<W: Write>(&mut self, writer: &mut EntryWriter<W>) -> Result<()> {
+        match self {
+            Overflow::Tempfile(q) => q.write_to(writer),
+            Overflow::Mem(q) => q.iter().try_for_each(|entry| writer.push(entry)),
+        }
+    }
+
+    fn state(&self) -> PathQueueState {
+        match self {
+            Overflow::Tempfile(q) => q.state(),
+            Overflow::Mem(q) if q.is_empty() => PathQueueState::Empty,
+            Overflow::Mem(_) => PathQueueState::PartiallyFilled,
+        }
+    }
+}
+
+/// The ring buffers and the overflow of a [`PathQueue`].
+///
+/// Entries are popped from `left`, then `mid`, then `right`, and pushed to `right`. When `right` is full, its entries
+/// move to `left` if the overflow is empty and to the overflow otherwise, so every entry in `left` is older than
+/// every entry in `mid`, which are older than those in `right`.
+#[derive(Debug)]
+struct Rings {
+    left:           MemPathQueue,
+    mid:            Option<Overflow>,
+    right:          MemPathQueue,
+    len:            usize,
+}
+
+/// A FIFO queue of directories that keeps the oldest and the newest entries in memory and spills the ones in
+/// between to disk.
+///
+/// All operations lock the queue, so it can be shared by any number of producers and consumers.
 #[derive(Debug)]
 pub struct PathQueue {
-    push_count:     AtomicUsize,
-    pop_count:      AtomicUsize,
-    pushing:        SpinLock,
-    popping:        SpinLock,
-    spilling:       SpinLock,
-    left:           UnsafeCell<MemPathQueue>,
-    mid:            UnsafeCell<Option<TempfilePathQueue>>,
-    right:          UnsafeCell<MemPathQueue>,
+    spill:          SpillOptions,
+    rings:          Mutex<Rings>,
 }
 
 impl PathQueue {
     pub fn new(read_buf_len: u32, write_buf_len: u32) -> Result<Self> {
+        Self::with_spill_options(read_buf_len, write_buf_len, SpillOptions::default())
+    }
+
+    pub fn with_spill_options(read_buf_len: u32, write_buf_len: u32, spill: SpillOptions) -> Result<Self> {
         assert!(read_buf_len > 0 && write_buf_len > 0);
+        if !spill.compression.is_available() {
+            return Err(Error::CompressionUnavailable(spill.compression));
+        }
+        let left = MemPathQueue::new(read_buf_len)?;
+        let right = MemPathQueue::new(write_buf_len)?;
+        spill.memory.reserve(((left.capacity + right.capacity) * size_of::<QueueEntry>()) as u64);
         Ok(PathQueue {
-            push_count: AtomicUsize::new(0),
-            pop_count: AtomicUsize::new(0),
-            pushing: SpinLock::new(),
-            popping: SpinLock::new(),
-            spilling: SpinLock::new(),
-            left: UnsafeCell::new(MemPathQueue::new(read_buf_len)),
-            mid: UnsafeCell::new(None),
-            right: UnsafeCell::new(MemPathQueue::new(write_buf_len)),
+            spill,
+            rings: Mutex::new(Rings { left, mid: None, right, len: 0 }),
         })
     }
 
-    pub fn push(&self, path: PathBuf) -> Result<Option<PathBuf>> {
-        if let Ok(_pushing) = self.pushing.try_lock() {
-            let left = unsafe { &mut *self.left.get() };
-            let mid = unsafe { &mut *self.mid.get() };
-            let right = unsafe { &mut *self.right.get() };
+    pub fn push(&self, path: QueueEntry) -> Result<()> {
+        let mut rings = self.rings.lock().unwrap();
+        let Rings { left, mid, right, len } = &mut *rings;
 
-            if let Some(path) = right.push(path) {
-                if let Ok(_spilling) = self.spilling.try_lock() {
-                    if mid.is_none() {
-                        *mid = Some(TempfilePathQueue::new()?);
-                    }
-                    let mid = unsafe { mid.as_mut().unwrap_unchecked() };
-                    if mid.state().is_empty() {
-                        while let Some(p) = right.pop() {
-                            if let Some(p) = left.push(p) {
-                                mid.push(&p)?;
-                            }
-                        }
-                    } else {
-                        while let Some(p) = right.pop() {
-                            mid.push(&p)?;
-                        }
+        let memory = &self.spill.memory;
+        // The budget is only enforced if the queue can spill.
+        let over_budget = if self.spill.disabled {
+            memory.reserve(heap_size(&path));
+            false
+        } else {
+            !memory.try_reserve(heap_size(&path))
+        };
+        if over_budget {
+            // Over budget: spill the write buffer, and the entry after it to keep the order.
+            let mid = match mid {
+                Some(mid) => mid,
+                None => mid.insert(Overflow::new(&self.spill)?),
+            };
+            while let Some(p) = right.pop() {
+                memory.release(heap_size(&p));
+                mid.push(p)?;
+            }
+            mid.push(path)?;
+        } else if let Some(path) = right.push(path) {
+            let mid = match mid {
+                Some(mid) => mid,
+                None => mid.insert(Overflow::new(&self.spill)?),
+            };
+            if mid.state().is_empty() {
+                while let Some(p) = right.pop() {
+                    if let Some(p) = left.push(p) {
+                        memory.release(heap_size(&p));
+                        mid.push(p)?;
                     }
-                    right.push(path);
-                } else {
-                    return Ok(Some(path));
+                }
+            } else {
+                while let Some(p) = right.pop() {
+                    memory.release(heap_size(&p));
+                    mid.push(p)?;
                 }
             }
-
-            self.push_count.fetch_add(1, Ordering::Release);
-
-            Ok(None)
-        } else {
-            Ok(Some(path))
+            right.push(path);
         }
+
+        *len += 1;
+        Ok(())
     }
 
-    pub fn pop(&self) -> Result<Option<PathBuf>> {
-        if let Ok(_popping) = self.popping.try_lock() {
-            let left = unsafe { &mut *self.left.get() };
-            let mid = unsafe { &mut *self.mid.get() };
-            let right = unsafe { &mut *self.right.get() };
+    pub fn pop(&self) -> Result<Option<QueueEntry>> {
+        let mut rings = self.rings.lock().unwrap();
+        let Rings { left, mid, right, len } = &mut *rings;
 
-            if Wrapping(self.push_count.load(Ordering::Acquire)) - Wrapping(self.pop_count.load(Ordering::Acquire)) == Wrapping(0) {
-                return Ok(None)
-            }
+        let memory = &self.spill.memory;
+        let path = if let Some(p) = left.pop() {
+            memory.release(heap_size(&p));
+            p
+        } else if let Some(p) = mid.as_mut().map(Overflow::pop).transpose()?.flatten() {
+            p
+        } else if let Some(p) = right.pop() {
+            memory.release(heap_size(&p));
+            p
+        } else {
+            return Ok(None);
+        };
 
-            let path;
-            if let Ok(_spilling) = self.spilling.try_lock() {
-                if let Some(p) = left.pop() {
-                    path = p;
-                } else if let Some(mid) = mid {
-                    if let Some(p) = mid.pop()? {
-                        path = p;
-                    } else if let Some(p) = right.pop() {
-                        path = p;
-                    } else {
-                        return Ok(None);
-                    }
-                } else if let Some(p) = right.pop() {
-                    path = p;
-                } else {
-                    return Ok(None);
-                }
-            } else {
-                return Ok(None);
-            }
+        *len -= 1;
+        Ok(Some(path))
+    }
 
-            self.pop_count.fetch_add(1, Ordering::Release);
+    pub fn len(&self) -> usize {
+        self.rings.lock().unwrap().len
+    }
 
-            Ok(Some(path))
-        } else {
-            Ok(None)
+    /// Writes the queued entries to `writer` in the order they would be popped, without removing them, so that
+    /// writing a checkpoint neither moves entries between memory and disk nor counts against the spill limit.
+    pub fn write_to<W: Write>(&self, writer: &mut EntryWriter<W>) -> Result<()> {
+        let mut rings = self.rings.lock().unwrap();
+        let Rings { left, mid, right, .. } = &mut *rings;
+        left.buf.iter().try_for_each(|entry| writer.push(entry))?;
+        if let Some(mid) = mid {
+            mid.write_to(writer)?;
         }
+        right.buf.iter().try_for_each(|entry| writer.push(entry))
     }
 
-    #[allow(dead_code)]
     pub fn is_empty(&self) -> bool {
-        Wrapping(self.push_count.load(Ordering::Acquire)) - Wrapping(self.pop_count.load(Ordering::Acquire)) == Wrapping(0)
+        self.len() == 0
     }
 
     #[cfg(test)]
     pub fn state(&self) -> (PathQueueState, PathQueueState, PathQueueState) {
-        loop {
-            if let Ok(_pushing) = self.pushing.try_lock() {
-                if let Ok(_popping) = self.popping.try_lock() {
-                    let left = unsafe { &*self.left.get() };
-                    let mid = unsafe { &*self.mid.get() };
-                    let right = unsafe { &*self.right.get() };
-                    if let Some(mid) = mid {
-                        return (left.state(), mid.state(), right.state())
-                    } else {
-                        return (left.state(), PathQueueState::Empty, right.state())
-                    }
-                }
-            }
-        }
+        let rings = self.rings.lock().unwrap();
+        let mid = rings.mid.as_ref().map_or(PathQueueState::Empty, Overflow::state);
+        (rings.left.state(), mid, rings.right.state())
     }
 }
 
-unsafe impl Sync for PathQueue {}
+impl Drop for PathQueue {
+    fn drop(&mut self) {
+        let memory = &self.spill.memory;
+        let rings = self.rings.get_mut().unwrap_or_else(PoisonError::into_inner);
+        for ring in [&rings.left, &rings.right] {
+            let queued: u64 = ring.buf.iter().map(heap_size).sum();
+            memory.release(queued + (ring.capacity * size_of::<QueueEntry>()) as u64);
+        }
+    }
+}
 
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 mod tests {
+    //! Tests that neither spill to disk nor read files also run under Miri, with
+    //! `cargo +nightly miri test --lib path_queue deque`.
+
     use super::*;
+    use proptest::collection::vec;
+    use proptest::option;
+    use proptest::prelude::*;
+    use proptest::sample::select;
+    use std::sync::atomic::AtomicU32;
     use std::thread;
 
+    fn entry(path: &str) -> QueueEntry {
+        QueueEntry::new(PathBuf::from(path))
+    }
+
+    fn no_spill() -> SpillOptions {
+        SpillOptions { disabled: true, ..SpillOptions::default() }
+    }
+
     #[test]
+    #[cfg_attr(miri, ignore)]
     fn single_thread() -> Result<()> {
         let q = PathQueue::new(2, 2)?;
-        q.push(PathBuf::from("1"))?;
+        q.push(entry("1"))?;
         assert_eq!(q.state(), (PathQueueState::Empty, PathQueueState::Empty, PathQueueState::PartiallyFilled));
-        q.push(PathBuf::from("2"))?;
+        q.push(entry("2"))?;
         assert_eq!(q.state(), (PathQueueState::Empty, PathQueueState::Empty, PathQueueState::Full));
-        q.push(PathBuf::from("3"))?;
+        q.push(entry("3"))?;
         assert_eq!(q.state(), (PathQueueState::Full, PathQueueState::Empty, PathQueueState::PartiallyFilled));
-        q.push(PathBuf::from("4"))?;
+        q.push(entry("4"))?;
         assert_eq!(q.state(), (PathQueueState::Full, PathQueueState::Empty, PathQueueState::Full));
-        q.push(PathBuf::from("5"))?;
+        q.push(entry("5"))?;
         assert_eq!(q.state(), (PathQueueState::Full, PathQueueState::PartiallyFilled, PathQueueState::PartiallyFilled));
-        q.push(PathBuf::from("6"))?;
+        q.push(entry("6"))?;
         assert_eq!(q.state(), (PathQueueState::Full, PathQueueState::PartiallyFilled, PathQueueState::Full));
-        assert_eq!(q.pop()?, Some(PathBuf::from("1")));
-        assert_eq!(q.pop()?, Some(PathBuf::from("2")));
-        assert_eq!(q.pop()?, Some(PathBuf::from("3")));
-        assert_eq!(q.pop()?, Some(PathBuf::from("4")));
-        assert_eq!(q.pop()?, Some(PathBuf::from("5")));
-        assert_eq!(q.pop()?, Some(PathBuf::from("6")));
+        assert_eq!(q.pop()?, Some(entry("1")));
+        assert_eq!(q.pop()?, Some(entry("2")));
+        assert_eq!(q.pop()?, Some(entry("3")));
+        assert_eq!(q.pop()?, Some(entry("4")));
+        assert_eq!(q.pop()?, Some(entry("5")));
+        assert_eq!(q.pop()?, Some(entry("6")));
         Ok(())
     }
 
     #[test]
     fn spsc() -> Result<()> {
         let queue = PathQueue::new(73, 131)?;
-        let count = 100000;
+        let count = if cfg!(miri) { 200 } else { 100000 };
         thread::scope(|s| -> Result<()> {
             s.spawn(|| -> Result<()> {
                 let mut i = 0;
                 loop {
-                    if let Some(path) = queue.pop()? {
-                        eprintln!("popped {}", path.display());
-                        assert_eq!(path.to_str().unwrap(), i.to_string());
+                    if let Some(entry) = queue.pop()? {
+                        eprintln!("popped {}", entry.path.display());
+                        assert_eq!(entry.path.to_str().unwrap(), i.to_string());
+                        assert_eq!(entry.depth, i);
                         i += 1;
                         if i == count {
                             break;
@@ -410,12 +1003,8 @@ mod tests {
             });
             s.spawn(|| -> Result<()> {
                 for i in 0..count {
-                    let mut path = PathBuf::from(i.to_string());
-                    let path_string = path.to_str().unwrap().to_string();
-                    while let Some(p) = queue.push(path)? {
-                        path = p;
-                    }
-                    eprintln!("pushed {}", path_string);
+                    queue.push(QueueEntry { depth: i, ..entry(&i.to_string()) })?;
+                    eprintln!("pushed {}", i);
                 }
                 Ok(())
             });
@@ -423,4 +1012,424 @@ mod tests {
         })?;
         Ok(())
     }
+
+    /// Pushes `count` entries from each of `producers` threads, tagged with the producer in `root` and numbered in
+    /// `depth`, and pops them from `consumers` threads. Every entry must be popped once, and each consumer must see
+    /// the entries of each producer in order.
+    fn mpmc(queue: &PathQueue, producers: u32, consumers: usize, count: u32) -> Result<()> {
+        let popped = AtomicU32::new(0);
+        let total = producers * count;
+        let mut seen = thread::scope(|s| -> Result<Vec<(u32, u32)>> {
+            for producer in 0..producers {
+                s.spawn(move || -> Result<()> {
+                    for i in 0..count {
+                        queue.push(QueueEntry { depth: i, root: producer, ..entry(&i.to_string()) })?;
+                    }
+                    Ok(())
+                });
+            }
+            let consumers: Vec<_> = (0..consumers).map(|_| {
+                let popped = &popped;
+                s.spawn(move || -> Result<Vec<(u32, u32)>> {
+                    let mut seen = Vec::new();
+                    let mut last = vec![None; producers as usize];
+                    while popped.load(Ordering::Acquire) < total {
+                        if let Some(e) = queue.pop()? {
+                            popped.fetch_add(1, Ordering::AcqRel);
+                            assert!(last[e.root as usize] < Some(e.depth), "{:?} after {:?}", e, last[e.root as usize]);
+                            last[e.root as usize] = Some(e.depth);
+                            seen.push((e.root, e.depth));
+                        } else {
+                            thread::yield_now();
+                        }
+                    }
+                    Ok(seen)
+                })
+            }).collect();
+            let mut seen = Vec::new();
+            for consumer in consumers {
+                seen.extend(consumer.join().unwrap()?);
+            }
+            Ok(seen)
+        })?;
+        seen.sort();
+        let expected: Vec<_> = (0..producers).flat_map(|p| (0..count).map(move |i| (p, i))).collect();
+        assert_eq!(seen, expected);
+        assert!(queue.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn mpsc() -> Result<()> {
+        let count = if cfg!(miri) { 50 } else { 20000 };
+        mpmc(&PathQueue::with_spill_options(3, 5, no_spill())?, 4, 1, count)
+    }
+
+    #[test]
+    fn mpmc_in_memory() -> Result<()> {
+        let count = if cfg!(miri) { 50 } else { 20000 };
+        mpmc(&PathQueue::with_spill_options(3, 5, no_spill())?, 4, 4, count)
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn mpmc_spilled() -> Result<()> {
+        mpmc(&PathQueue::with_spill_options(3, 5, SpillOptions::default())?, 4, 4, 20000)
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn mpmc_stress() -> Result<()> {
+        for (read_len, write_len) in [(1, 1), (2, 3), (64, 64)] {
+            mpmc(&PathQueue::with_spill_options(read_len, write_len, SpillOptions::default())?, 16, 16, 5000)?;
+        }
+        // A budget that only fits the rings makes every other push spill.
+        let memory = MemoryBudget::new(2 * 4 * size_of::<QueueEntry>() as u64);
+        mpmc(&PathQueue::with_spill_options(4, 4, SpillOptions { memory, ..SpillOptions::default() })?, 16, 16, 5000)
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        /// Push an entry whose path has a component of this length.
+        Push(usize),
+        Pop,
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            3 => (0..300usize).prop_map(Op::Push),
+            2 => Just(Op::Pop),
+        ]
+    }
+
+    fn compressions() -> Vec<Compression> {
+        [Compression::None, Compression::Lz4, Compression::Zstd].into_iter().filter(|c| c.is_available()).collect()
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        /// Tiny ring buffers move entries between the rings, the overflow and the blocks of the spill file all the
+        /// time. Whatever the interleaving of pushes and pops, the queue must behave like a `VecDeque`.
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn fifo_across_spill_boundaries(
+            ops in vec(op(), 0..3000),
+            read_len in 1..5u32,
+            write_len in 1..5u32,
+            spill in any::<bool>(),
+            budget in option::of(0..4096u64),
+            compression in select(compressions()),
+        ) {
+            let rings = (read_len.next_power_of_two() + write_len.next_power_of_two()) as u64 * size_of::<QueueEntry>() as u64;
+            let memory = budget.map_or_else(MemoryBudget::unlimited, |paths| MemoryBudget::new(rings + paths));
+            let options = SpillOptions { disabled: !spill, compression, memory: memory.clone(), ..SpillOptions::default() };
+            let q = PathQueue::with_spill_options(read_len, write_len, options)?;
+            let mut model = VecDeque::new();
+            for (i, op) in ops.into_iter().enumerate() {
+                match op {
+                    Op::Push(len) => {
+                        let path = PathBuf::from(format!("{}/{}", i, "x".repeat(len)));
+                        let e = QueueEntry { depth: i as u32, ino: i as u64, ..QueueEntry::new(path) };
+                        model.push_back(e.clone());
+                        q.push(e)?;
+                    },
+                    Op::Pop => prop_assert_eq!(q.pop()?, model.pop_front()),
+                }
+                prop_assert_eq!(q.len(), model.len());
+                if spill {
+                    prop_assert!(memory.used() <= memory.limit());
+                }
+            }
+            while let Some(e) = model.pop_front() {
+                prop_assert_eq!(q.pop()?, Some(e));
+            }
+            prop_assert_eq!(q.pop()?, None);
+            drop(q);
+            prop_assert_eq!(memory.used(), 0);
+        }
+    }
+
+    #[test]
+    fn drops_remaining_entries() -> Result<()> {
+        // Miri reports the paths as leaked if they are not dropped.
+        let budget = MemoryBudget::unlimited();
+        let q = PathQueue::with_spill_options(2, 2, SpillOptions { memory: budget.clone(), ..no_spill() })?;
+        for i in 0..7 {
+            q.push(entry(&i.to_string()))?;
+        }
+        assert_eq!(q.len(), 7);
+        assert_eq!(q.pop()?, Some(entry("0")));
+        drop(q);
+        assert_eq!(budget.used(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn capacity_overflow() {
+        assert!(matches!(MemPathQueue::new(u32::MAX), Err(Error::CapacityOverflow { .. })));
+        assert!(matches!(PathQueue::new(u32::MAX, 1), Err(Error::CapacityOverflow { .. })));
+        let capacity = isize::MAX as usize / size_of::<QueueEntry>() + 1;
+        assert!(matches!(MemPathQueue::with_capacity(capacity), Err(Error::CapacityOverflow { capacity: c }) if c == capacity));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn allocation_failure() {
+        // The largest buffer whose size fits in isize, which no allocator can provide.
+        let capacity = isize::MAX as usize / size_of::<QueueEntry>();
+        assert!(matches!(MemPathQueue::with_capacity(capacity), Err(Error::OutOfMemory { capacity: c }) if c == capacity));
+        let mut q = MemPathQueue::new(2).unwrap();
+        assert_eq!(q.push(entry("a")), None);
+        assert_eq!(q.push(entry("b")), None);
+        assert_eq!(q.push(entry("c")), Some(entry("c")));
+        assert_eq!(q.pop(), Some(entry("a")));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn tempfile_roundtrip() -> Result<()> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let entries = [
+            QueueEntry { path: PathBuf::from("a/b"), depth: 2, root: 1, dev: 0x1234, ino: u64::MAX, kind: FileKind::Dir },
+            QueueEntry { path: PathBuf::from(OsStr::from_bytes(b"\xff\0\xfe")), depth: 0, root: 0, dev: 0, ino: 7, kind: FileKind::Symlink },
+            entry(""),
+        ];
+        let mut q = TempfilePathQueue::new(SpillOptions { compression: Compression::None, ..SpillOptions::default() })?;
+        for e in &entries {
+            q.push(e)?;
+        }
+        for e in &entries {
+            assert_eq!(q.pop()?.as_ref(), Some(e));
+        }
+        assert_eq!(q.pop()?, None);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn tempfile_blocks() -> Result<()> {
+        for compression in [Compression::None, Compression::Lz4, Compression::Zstd] {
+            if !compression.is_available() {
+                continue;
+            }
+            let options = SpillOptions { compression, ..SpillOptions::default() };
+            let mut q = TempfilePathQueue::new(options.clone())?;
+            let path = |i: u32| PathBuf::from(format!("/some/long/parent/directory/{}/{}", i / 100, i));
+            let count = 20000;
+            // Interleave pops with the second half of the pushes, so that both blocks in the file and the block being
+            // filled are popped.
+            let mut popped = 0;
+            for i in 0..count {
+                q.push(&QueueEntry { depth: i, ..QueueEntry::new(path(i)) })?;
+                if i >= count / 2 && i % 2 == 0 {
+                    let e = q.pop()?.unwrap();
+                    assert_eq!((e.path, e.depth), (path(popped), popped));
+                    popped += 1;
+                }
+            }
+            assert!(q.blocks_written > 1);
+            while let Some(e) = q.pop()? {
+                assert_eq!((e.path, e.depth), (path(popped), popped));
+                popped += 1;
+            }
+            assert_eq!(popped, count);
+            let file_len = q.writer.metadata()?.len();
+            assert!(file_len < count as u64 * 16, "{:?}: {} bytes", compression, file_len);
+            assert_eq!((options.bytes_written(), options.bytes_read()), (file_len, file_len));
+            assert_eq!(options.entries_spilled(), 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn entry_writer_and_reader() -> Result<()> {
+        let count = if cfg!(miri) { 100 } else { 20000 };
+        let path = |i: u32| PathBuf::from(format!("/some/long/parent/directory/{}/{}", i / 100, i));
+        let mut w = EntryWriter::new(Vec::new(), Compression::None)?;
+        for i in 0..count {
+            w.push(&QueueEntry { depth: i, ..QueueEntry::new(path(i)) })?;
+        }
+        let mut data = w.finish()?;
+        data.extend_from_slice(b"trailer");
+        let mut r = EntryReader::new(&data[..]);
+        for i in 0..count {
+            assert_eq!(r.next().transpose()?, Some(QueueEntry { depth: i, ..QueueEntry::new(path(i)) }));
+        }
+        assert!(r.next().is_none());
+        assert_eq!(r.into_inner(), b"trailer");
+        let empty = EntryWriter::new(Vec::new(), Compression::None)?.finish()?;
+        assert_eq!(EntryReader::new(&empty[..]).count(), 0);
+
+        // A corrupt length is rejected before allocating a buffer for it.
+        for offset in [4, 8] {
+            let mut corrupt = data.clone();
+            corrupt[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(matches!(EntryReader::new(&corrupt[..]).next(), Some(Err(Error::CorruptSpillFile))));
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn spill_options() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fill = |q: &PathQueue, count: u32| -> Result<()> {
+            for i in 0..count {
+                q.push(entry(&format!("{:0>100}", i)))?;
+            }
+            Ok(())
+        };
+
+        // With spilling disabled, the overflow stays in memory.
+        let q = PathQueue::with_spill_options(2, 2, no_spill())?;
+        fill(&q, 100000)?;
+        assert_eq!(q.state().1, PathQueueState::PartiallyFilled);
+        for i in 0..100000 {
+            assert_eq!(q.pop()?.unwrap().path.to_str().unwrap(), format!("{:0>100}", i));
+        }
+
+        // The limit is shared by all queues created from the same options.
+        let options = SpillOptions {
+            dir: Some(dir.path().to_path_buf()),
+            max_size: Some(SPILL_BLOCK_SIZE as u64 * 4),
+            compression: Compression::None,
+            ..SpillOptions::default()
+        };
+        let q1 = PathQueue::with_spill_options(2, 2, options.clone())?;
+        let q2 = PathQueue::with_spill_options(2, 2, options.clone())?;
+        fill(&q1, 10000)?;
+        assert!(options.bytes_written() > 0);
+        assert!(matches!(fill(&q2, 100000), Err(Error::SpillLimitExceeded { .. })));
+        assert!(options.bytes_written() <= SPILL_BLOCK_SIZE as u64 * 4);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn memory_budget() -> Result<()> {
+        let ring_size = 2 * 8 * size_of::<QueueEntry>() as u64;
+        let path_size = 100;
+        let budget = MemoryBudget::new(ring_size + 4 * path_size);
+        let options = SpillOptions { memory: budget.clone(), ..SpillOptions::default() };
+        let q = PathQueue::with_spill_options(8, 8, options)?;
+        assert_eq!(budget.used(), ring_size);
+        for i in 0..20 {
+            let mut path = PathBuf::with_capacity(path_size as usize);
+            path.push(i.to_string());
+            q.push(QueueEntry::new(path))?;
+            assert!(budget.used() <= budget.limit());
+        }
+        // Every fifth push finds the budget used up and spills the four entries before it.
+        assert_eq!(budget.used(), ring_size);
+        assert_eq!(q.state(), (PathQueueState::Empty, PathQueueState::PartiallyFilled, PathQueueState::Empty));
+        for i in 0..20 {
+            assert_eq!(q.pop()?, Some(entry(&i.to_string())));
+        }
+        assert_eq!(budget.used(), ring_size);
+        assert_eq!(budget.peak(), budget.limit());
+        drop(q);
+        assert_eq!(budget.used(), 0);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    //! Run with `LOOM_MAX_PREEMPTIONS=3 RUSTFLAGS="--cfg loom" cargo test --release --lib loom_tests`.
+
+    use super::*;
+    use loom::sync::Arc;
+    use loom::thread;
+
+    /// A queue whose rings hold one entry each, so that a few entries go through all three parts.
+    fn queue() -> Arc<PathQueue> {
+        Arc::new(PathQueue::with_spill_options(1, 1, SpillOptions { disabled: true, ..SpillOptions::default() }).unwrap())
+    }
+
+    fn entry(producer: u32, i: u32) -> QueueEntry {
+        QueueEntry { depth: i, root: producer, ..QueueEntry::new(PathBuf::from(i.to_string())) }
+    }
+
+    fn producer(q: &Arc<PathQueue>, producer: u32, count: u32) -> thread::JoinHandle<()> {
+        let q = q.clone();
+        thread::spawn(move || {
+            for i in 0..count {
+                q.push(entry(producer, i)).unwrap();
+            }
+        })
+    }
+
+    /// Pops up to `max` entries without waiting for more.
+    fn consumer(q: &Arc<PathQueue>, max: usize) -> thread::JoinHandle<Vec<(u32, u32)>> {
+        let q = q.clone();
+        thread::spawn(move || {
+            (0..max).map_while(|_| q.pop().unwrap()).map(|e| (e.root, e.depth)).collect()
+        })
+    }
+
+    fn drain(q: &PathQueue) -> Vec<(u32, u32)> {
+        std::iter::from_fn(|| q.pop().unwrap()).map(|e| (e.root, e.depth)).collect()
+    }
+
+    /// Checks that the entries of each producer are in order and, once everything is popped, that none is missing.
+    fn check(popped: &[Vec<(u32, u32)>], producers: u32, count: u32) {
+        for seen in popped {
+            for p in 0..producers {
+                let order: Vec<_> = seen.iter().filter(|e| e.0 == p).map(|e| e.1).collect();
+                assert!(order.windows(2).all(|w| w[0] < w[1]), "{:?}", seen);
+            }
+        }
+        let mut all: Vec<_> = popped.concat();
+        all.sort();
+        let expected: Vec<_> = (0..producers).flat_map(|p| (0..count).map(move |i| (p, i))).collect();
+        assert_eq!(all, expected);
+    }
+
+    #[test]
+    fn spsc() {
+        loom::model(|| {
+            let q = queue();
+            let p = producer(&q, 0, 3);
+            let c = consumer(&q, 3);
+            p.join().unwrap();
+            let first = c.join().unwrap();
+            let rest = drain(&q);
+            // A single consumer sees everything in order.
+            let all = [first, rest].concat();
+            assert_eq!(all, [(0, 0), (0, 1), (0, 2)]);
+            assert!(q.is_empty());
+        });
+    }
+
+    #[test]
+    fn mpsc() {
+        loom::model(|| {
+            let q = queue();
+            let producers = [producer(&q, 0, 2), producer(&q, 1, 2)];
+            let c = consumer(&q, 2);
+            for p in producers {
+                p.join().unwrap();
+            }
+            let first = c.join().unwrap();
+            let rest = drain(&q);
+            check(&[[first, rest].concat()], 2, 2);
+        });
+    }
+
+    #[test]
+    fn mpmc() {
+        loom::model(|| {
+            let q = queue();
+            let producers = [producer(&q, 0, 2), producer(&q, 1, 2)];
+            let consumers = [consumer(&q, 1), consumer(&q, 1)];
+            for p in producers {
+                p.join().unwrap();
+            }
+            let mut popped: Vec<_> = consumers.into_iter().map(|c| c.join().unwrap()).collect();
+            popped.push(drain(&q));
+            check(&popped, 2, 2);
+        });
+    }
 }
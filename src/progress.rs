@@ -0,0 +1,131 @@
+//! The report printed to stderr by `--progress`.
+
+use std::io;
+use std::io::IsTerminal;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+use bfind::Monitor;
+use bfind::Progress;
+
+/// How often the report is redrawn on a terminal.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often a record is written when stderr is not a terminal.
+const RECORD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reports the progress of a traversal from a background thread until dropped.
+pub struct Reporter {
+    stop:       Arc<(Mutex<bool>, Condvar)>,
+    handle:     Option<JoinHandle<()>>,
+}
+
+impl Reporter {
+    pub fn start(prog: &str, monitor: Monitor) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let prog = prog.to_string();
+        let handle = {
+            let stop = stop.clone();
+            thread::spawn(move || report(&prog, &monitor, &stop))
+        };
+        Self { stop, handle: Some(handle) }
+    }
+}
+
+impl Drop for Reporter {
+    fn drop(&mut self) {
+        let (stopped, cond) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        cond.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn report(prog: &str, monitor: &Monitor, stop: &(Mutex<bool>, Condvar)) {
+    let tty = io::stderr().is_terminal();
+    let interval = if tty { REDRAW_INTERVAL } else { RECORD_INTERVAL };
+    let start = Instant::now();
+    let mut last = (start, Progress::default());
+    let (stopped, cond) = stop;
+    let mut guard = stopped.lock().unwrap();
+    loop {
+        guard = cond.wait_timeout_while(guard, interval, |stopped| !*stopped).unwrap().0;
+        let now = Instant::now();
+        let progress = monitor.progress();
+        // Entries per second since the previous report.
+        let secs = now.duration_since(last.0).as_secs_f64();
+        let rate = if secs > 0.0 { (progress.entries_seen - last.1.entries_seen) as f64 / secs } else { 0.0 };
+        last = (now, progress);
+
+        let mut stderr = io::stderr().lock();
+        let elapsed = now.duration_since(start).as_secs_f64();
+        if tty {
+            let _ = write!(stderr, "\r\x1b[K{}", status_line(&progress, elapsed, rate));
+            if *guard {
+                let _ = write!(stderr, "\r\x1b[K");
+            }
+        } else {
+            let _ = writeln!(stderr, "{}: progress {}", prog, record(&progress, elapsed, rate));
+        }
+        let _ = stderr.flush();
+        if *guard {
+            break;
+        }
+    }
+}
+
+fn in_memory(p: &Progress) -> u64 {
+    (p.queued as u64).saturating_sub(p.spilled)
+}
+
+/// The report redrawn on a terminal.
+fn status_line(p: &Progress, elapsed: f64, rate: f64) -> String {
+    format!(
+        "{:.0}s: {} dirs, {} entries, {} matches, depth {}, queued {} in memory + {} spilled, {:.0} entries/s",
+        elapsed,
+        p.dirs_read,
+        p.entries_seen,
+        p.yielded,
+        p.depth,
+        in_memory(p),
+        p.spilled,
+        rate,
+    )
+}
+
+/// The report as a line of `key=value` fields.
+fn record(p: &Progress, elapsed: f64, rate: f64) -> String {
+    format!(
+        "elapsed={:.3} dirs={} entries={} matches={} depth={} queued_memory={} queued_spilled={} entries_per_sec={:.0}",
+        elapsed,
+        p.dirs_read,
+        p.entries_seen,
+        p.yielded,
+        p.depth,
+        in_memory(p),
+        p.spilled,
+        rate,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let p = Progress { dirs_read: 3, entries_seen: 40, yielded: 20, depth: 2, queued: 5, spilled: 2 };
+        assert_eq!(record(&p, 1.5, 26.6),
+            "elapsed=1.500 dirs=3 entries=40 matches=20 depth=2 queued_memory=3 queued_spilled=2 entries_per_sec=27");
+        assert_eq!(status_line(&p, 1.5, 26.6),
+            "2s: 3 dirs, 40 entries, 20 matches, depth 2, queued 3 in memory + 2 spilled, 27 entries/s");
+    }
+}
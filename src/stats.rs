@@ -0,0 +1,163 @@
+//! The summary printed to stderr by `--stats` and `--stats-json` at the end of a run.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use bfind::Stats;
+
+use crate::json::Value;
+
+/// Statistics of a run of bfind: those of the traversal, and what only the program knows.
+pub struct Summary {
+    pub walk:       Stats,
+
+    /// Number of errors by kind, which is the kind of the I/O error for I/O errors.
+    pub errors:     BTreeMap<String, u64>,
+
+    pub wall:       Duration,
+
+    /// User and system CPU time of the process, if known.
+    pub cpu:        Option<(Duration, Duration)>,
+}
+
+/// Names the kind of an error, for counting errors by kind.
+pub fn error_kind(e: &bfind::Error) -> String {
+    match e {
+        bfind::Error::Io { source, .. } => format!("{:?}", source.kind()),
+        bfind::Error::Loop { .. } => "Loop".to_string(),
+        bfind::Error::PathQueue { .. } => "PathQueue".to_string(),
+        _ => "Other".to_string(),
+    }
+}
+
+/// User and system CPU time used by the process so far.
+#[cfg(unix)]
+pub fn cpu_time() -> Option<(Duration, Duration)> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: getrusage fills in `usage` when it returns 0.
+    let usage = unsafe {
+        if libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) != 0 {
+            return None;
+        }
+        usage.assume_init()
+    };
+    let duration = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    Some((duration(usage.ru_utime), duration(usage.ru_stime)))
+}
+
+#[cfg(not(unix))]
+pub fn cpu_time() -> Option<(Duration, Duration)> {
+    None
+}
+
+fn human_bytes(n: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = n as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", n)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+impl Summary {
+    pub fn human(&self) -> String {
+        let walk = &self.walk;
+        let p = &walk.progress;
+        let mut s = String::new();
+        let errors: u64 = self.errors.values().sum();
+        writeln!(s, "entries:      {}", p.entries_seen).unwrap();
+        writeln!(s, "matches:      {}", p.yielded).unwrap();
+        writeln!(s, "directories:  {}", p.dirs_read).unwrap();
+        write!(s, "errors:       {}", errors).unwrap();
+        if errors > 0 {
+            let kinds: Vec<_> = self.errors.iter().map(|(kind, n)| format!("{} {}", n, kind)).collect();
+            write!(s, " ({})", kinds.join(", ")).unwrap();
+        }
+        writeln!(s).unwrap();
+        writeln!(s, "max depth:    {}", walk.max_depth).unwrap();
+        writeln!(s, "steals:       {}", walk.steals).unwrap();
+        writeln!(s, "spilled:      {} written, {} read",
+                 human_bytes(walk.spill_bytes_written), human_bytes(walk.spill_bytes_read)).unwrap();
+        writeln!(s, "peak memory:  {}", human_bytes(walk.peak_memory)).unwrap();
+        writeln!(s, "wall time:    {:.3}s", self.wall.as_secs_f64()).unwrap();
+        if let Some((user, system)) = self.cpu {
+            writeln!(s, "cpu time:     {:.3}s ({:.3}s user, {:.3}s system)",
+                     (user + system).as_secs_f64(), user.as_secs_f64(), system.as_secs_f64()).unwrap();
+        }
+        for (i, (working, sleeping)) in walk.threads.iter().enumerate() {
+            writeln!(s, "thread {:<2}     {:.3}s working, {:.3}s sleeping",
+                     i, working.as_secs_f64(), sleeping.as_secs_f64()).unwrap();
+        }
+        s
+    }
+
+    pub fn json(&self) -> Value {
+        let walk = &self.walk;
+        let p = &walk.progress;
+        let secs = |d: Duration| Value::from(d.as_secs_f64());
+        Value::object([
+            ("entries", p.entries_seen.into()),
+            ("matches", p.yielded.into()),
+            ("directories", p.dirs_read.into()),
+            ("errors", Value::object(self.errors.iter().map(|(kind, n)| (kind.clone(), Value::from(*n))))),
+            ("max_depth", walk.max_depth.into()),
+            ("steals", walk.steals.into()),
+            ("spill_bytes_written", walk.spill_bytes_written.into()),
+            ("spill_bytes_read", walk.spill_bytes_read.into()),
+            ("peak_memory_bytes", walk.peak_memory.into()),
+            ("wall_seconds", secs(self.wall)),
+            ("user_seconds", self.cpu.map_or(Value::Null, |(user, _)| secs(user))),
+            ("system_seconds", self.cpu.map_or(Value::Null, |(_, system)| secs(system))),
+            ("threads", Value::Array(walk.threads.iter().map(|&(working, sleeping)| {
+                Value::object([("working_seconds", secs(working)), ("sleeping_seconds", secs(sleeping))])
+            }).collect())),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary() -> Summary {
+        let mut walk = Stats::default();
+        walk.progress.entries_seen = 10;
+        walk.progress.yielded = 9;
+        walk.progress.dirs_read = 3;
+        walk.max_depth = 2;
+        walk.spill_bytes_written = 3 * 1024 * 1024 / 2;
+        walk.peak_memory = 2048;
+        walk.threads = vec![(Duration::from_millis(1500), Duration::from_millis(250))];
+        Summary {
+            walk,
+            errors: BTreeMap::from([("PermissionDenied".to_string(), 2), ("Loop".to_string(), 1)]),
+            wall: Duration::from_secs(2),
+            cpu: Some((Duration::from_secs(1), Duration::from_millis(500))),
+        }
+    }
+
+    #[test]
+    fn human() {
+        let s = summary().human();
+        assert!(s.contains("errors:       3 (1 Loop, 2 PermissionDenied)\n"), "{}", s);
+        assert!(s.contains("spilled:      1.5 MiB written, 0 B read\n"), "{}", s);
+        assert!(s.contains("peak memory:  2.0 KiB\n"), "{}", s);
+        assert!(s.contains("cpu time:     1.500s (1.000s user, 0.500s system)\n"), "{}", s);
+        assert!(s.contains("thread 0      1.500s working, 0.250s sleeping\n"), "{}", s);
+    }
+
+    #[test]
+    fn json() {
+        let s = summary().json().to_string();
+        assert!(s.starts_with(r#"{"entries":10,"matches":9,"directories":3,"errors":{"Loop":1,"PermissionDenied":2},"#), "{}", s);
+        assert!(s.contains(r#","spill_bytes_read":0,"peak_memory_bytes":2048,"#), "{}", s);
+        assert!(s.ends_with(r#""threads":[{"working_seconds":1.5,"sleeping_seconds":0.25}]}"#), "{}", s);
+    }
+}
@@ -0,0 +1,41 @@
+//! Synchronization primitives, from loom when testing with `--cfg loom` and from std otherwise.
+
+#[cfg(not(loom))]
+pub use std::sync::Arc;
+#[cfg(not(loom))]
+pub use std::sync::Mutex;
+#[cfg(not(loom))]
+pub use std::sync::atomic::AtomicU32;
+#[cfg(not(loom))]
+pub use std::sync::atomic::AtomicU64;
+#[cfg(not(loom))]
+pub use std::sync::atomic::Ordering;
+
+#[cfg(loom)]
+pub use loom::cell::UnsafeCell;
+#[cfg(loom)]
+pub use loom::sync::Arc;
+#[cfg(loom)]
+pub use loom::sync::Mutex;
+#[cfg(loom)]
+pub use loom::sync::atomic::AtomicU32;
+#[cfg(loom)]
+pub use loom::sync::atomic::AtomicU64;
+#[cfg(loom)]
+pub use loom::sync::atomic::Ordering;
+
+/// `std::cell::UnsafeCell` with the interface of `loom::cell::UnsafeCell`.
+#[cfg(not(loom))]
+#[derive(Debug)]
+pub struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    pub fn new(value: T) -> Self {
+        Self(std::cell::UnsafeCell::new(value))
+    }
+
+    pub fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+}
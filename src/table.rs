@@ -0,0 +1,147 @@
+//! The `--csv` and `--tsv` outputs: a header row with the names of the columns, then a row per entry.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use bfind::DirEntry;
+
+use crate::fields;
+use crate::fields::Field;
+use crate::hash::Algorithm;
+use crate::hash::Digests;
+
+/// Columns printed when `--columns` is not given.
+pub const DEFAULT_COLUMNS: &[Field] = &[Field::Path, Field::Type, Field::Size, Field::Mtime];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    /// Comma-separated, with fields quoted as in RFC 4180 when needed.
+    Csv,
+
+    /// Tab-separated, with tabs, newlines and backslashes in fields escaped with backslashes.
+    Tsv,
+}
+
+pub struct Table {
+    dialect:    Dialect,
+    columns:    Vec<Field>,
+}
+
+/// Parses the argument of `--columns`, a comma-separated list of field names.
+pub fn parse_columns(s: &str) -> Result<Vec<Field>, String> {
+    s.split(',').map(|name| Field::from_name(name.trim()).ok_or_else(|| name.to_string())).collect()
+}
+
+impl Table {
+    pub fn new(dialect: Dialect, columns: Vec<Field>) -> Self {
+        Self { dialect, columns }
+    }
+
+    pub fn needs_metadata(&self) -> bool {
+        self.columns.iter().any(|field| field.needs_metadata())
+    }
+
+    pub fn uses(&self, field: Field) -> bool {
+        self.columns.contains(&field)
+    }
+
+    /// The hash algorithms of the columns, which are computed before the entries are printed.
+    pub fn algorithms(&self) -> Vec<Algorithm> {
+        let mut algorithms = Vec::new();
+        for field in &self.columns {
+            algorithms.extend(field.algorithm().filter(|algorithm| !algorithms.contains(algorithm)));
+        }
+        algorithms
+    }
+
+    pub fn header(&self) -> String {
+        let mut s = String::new();
+        for (i, field) in self.columns.iter().enumerate() {
+            if i > 0 {
+                s.push(self.separator());
+            }
+            self.push_field(&mut s, field.name());
+        }
+        s
+    }
+
+    /// Appends the row of an entry to `out`, without a line break.
+    pub fn render(&self, out: &mut String, entry: &DirEntry, shown: &Path, metadata: Option<&fs::Metadata>,
+                  digests: &Digests) {
+        let mut value = String::new();
+        for (i, field) in self.columns.iter().enumerate() {
+            if i > 0 {
+                out.push(self.separator());
+            }
+            value.clear();
+            write!(value, "{}", fields::value(*field, entry, shown, metadata, digests)).unwrap();
+            self.push_field(out, &value);
+        }
+    }
+
+    fn separator(&self) -> char {
+        match self.dialect {
+            Dialect::Csv => ',',
+            Dialect::Tsv => '\t',
+        }
+    }
+
+    fn push_field(&self, out: &mut String, value: &str) {
+        match self.dialect {
+            Dialect::Csv if value.contains([',', '"', '\n', '\r']) => {
+                out.push('"');
+                out.push_str(&value.replace('"', "\"\""));
+                out.push('"');
+            },
+            Dialect::Csv => out.push_str(value),
+            Dialect::Tsv => {
+                for c in value.chars() {
+                    match c {
+                        '\t' => out.push_str("\\t"),
+                        '\n' => out.push_str("\\n"),
+                        '\r' => out.push_str("\\r"),
+                        '\\' => out.push_str("\\\\"),
+                        c => out.push(c),
+                    }
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoting() {
+        let columns = parse_columns("name, size,depth").unwrap();
+        assert_eq!(columns, [Field::Name, Field::Size, Field::Depth]);
+        assert_eq!(parse_columns("name,nope"), Err("nope".to_string()));
+
+        let csv = Table::new(Dialect::Csv, columns.clone());
+        let tsv = Table::new(Dialect::Tsv, columns);
+        assert_eq!(csv.header(), "name,size,depth");
+        assert_eq!(tsv.header(), "name\tsize\tdepth");
+        let mut out = String::new();
+        csv.push_field(&mut out, "plain");
+        out.push('|');
+        csv.push_field(&mut out, "a,\"b\"\nc");
+        assert_eq!(out, "plain|\"a,\"\"b\"\"\nc\"");
+        out.clear();
+        tsv.push_field(&mut out, "a\tb\\c\nd,\"e\"");
+        assert_eq!(out, "a\\tb\\\\c\\nd,\"e\"");
+    }
+
+    #[test]
+    fn rows() {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        fs::write(root.path().join("a,b"), "hello").unwrap();
+        let entry = bfind::WalkBuilder::new(root.path()).build().unwrap().next().unwrap().unwrap();
+        let table = Table::new(Dialect::Csv, parse_columns("name,size,depth,target").unwrap());
+        let mut out = String::new();
+        table.render(&mut out, &entry, Path::new("a,b"), entry.metadata().ok(), &Digests::default());
+        assert_eq!(out, "\"a,b\",5,1,");
+    }
+}
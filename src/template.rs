@@ -0,0 +1,213 @@
+//! The templates of the `print` action, like `{name:10} {size:>10}`.
+//!
+//! A placeholder is a field name in braces, optionally followed by a colon, an alignment (`<`, `>` or `^`) and a
+//! width. Numbers are aligned to the right and text to the left by default. `{{` and `}}` stand for literal braces.
+
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+
+use bfind::DirEntry;
+
+use crate::fields;
+use crate::fields::Field;
+use crate::hash::Algorithm;
+use crate::hash::Digests;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Error {
+    #[error("unknown placeholder \"{{{0}}}\"")]
+    UnknownPlaceholder(String),
+
+    #[error("invalid format \"{0}\" in placeholder")]
+    InvalidSpec(String),
+
+    #[error("unmatched \"{0}\" in template")]
+    UnmatchedBrace(char),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field {
+        field:  Field,
+        align:  Option<Align>,
+        width:  usize,
+    },
+}
+
+#[derive(Debug)]
+pub struct Template {
+    parts:      Vec<Part>,
+}
+
+fn parse_placeholder(s: &str) -> Result<Part> {
+    let (name, spec) = s.split_once(':').unwrap_or((s, ""));
+    let field = Field::from_name(name).ok_or_else(|| Error::UnknownPlaceholder(name.to_string()))?;
+    let (align, width) = match spec.chars().next() {
+        Some('<') => (Some(Align::Left), &spec[1..]),
+        Some('>') => (Some(Align::Right), &spec[1..]),
+        Some('^') => (Some(Align::Center), &spec[1..]),
+        _ => (None, spec),
+    };
+    let width = if width.is_empty() {
+        0
+    } else {
+        width.parse().map_err(|_| Error::InvalidSpec(spec.to_string()))?
+    };
+    Ok(Part::Field { field, align, width })
+}
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '{' if chars.next_if(|&(_, c)| c == '{').is_some() => literal.push('{'),
+                '}' if chars.next_if(|&(_, c)| c == '}').is_some() => literal.push('}'),
+                '{' => {
+                    let end = s[i..].find('}').ok_or(Error::UnmatchedBrace('{'))? + i;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(parse_placeholder(&s[i + 1..end])?);
+                    while chars.next_if(|&(j, _)| j <= end).is_some() {}
+                },
+                '}' => return Err(Error::UnmatchedBrace('}')),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Self { parts })
+    }
+
+    pub fn needs_metadata(&self) -> bool {
+        self.parts.iter().any(|part| matches!(part, Part::Field { field, .. } if field.needs_metadata()))
+    }
+
+    pub fn uses(&self, field: Field) -> bool {
+        self.parts.iter().any(|part| matches!(part, Part::Field { field: f, .. } if *f == field))
+    }
+
+    /// The hash algorithms of the placeholders, which are computed before the entries are printed.
+    pub fn algorithms(&self) -> Vec<Algorithm> {
+        let mut algorithms = Vec::new();
+        for part in &self.parts {
+            if let Part::Field { field, .. } = part {
+                algorithms.extend(field.algorithm().filter(|algorithm| !algorithms.contains(algorithm)));
+            }
+        }
+        algorithms
+    }
+
+    /// Appends the template filled in with the fields of an entry to `out`.
+    pub fn render(&self, out: &mut String, entry: &DirEntry, shown: &Path, metadata: Option<&fs::Metadata>,
+                  digests: &Digests) {
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => out.push_str(s),
+                Part::Field { field, align, width } => {
+                    let value = fields::value(*field, entry, shown, metadata, digests);
+                    push_aligned(out, &value, *align, *width);
+                },
+            }
+        }
+    }
+
+    /// Fills in the template as an argument of `exec`. Paths without a width are copied as they are rather than
+    /// displayed, so that names which are not valid UTF-8 reach the command unchanged.
+    pub fn render_os(&self, entry: &DirEntry, shown: &Path, metadata: Option<&fs::Metadata>,
+                     digests: &Digests) -> OsString {
+        let mut out = OsString::new();
+        let mut text = String::new();
+        for part in &self.parts {
+            text.clear();
+            match part {
+                Part::Literal(s) => out.push(s),
+                Part::Field { field, align, width } => match fields::value(*field, entry, shown, metadata, digests) {
+                    fields::FieldValue::Path(path) if *width == 0 => out.push(path.as_os_str()),
+                    value => {
+                        push_aligned(&mut text, &value, *align, *width);
+                        out.push(&text);
+                    },
+                },
+            }
+        }
+        out
+    }
+}
+
+fn push_aligned(out: &mut String, value: &fields::FieldValue, align: Option<Align>, width: usize) {
+    let align = align.unwrap_or(if value.is_numeric() { Align::Right } else { Align::Left });
+    let _ = match align {
+        Align::Left => write!(out, "{:<1$}", value.to_string(), width),
+        Align::Right => write!(out, "{:>1$}", value.to_string(), width),
+        Align::Center => write!(out, "{:^1$}", value.to_string(), width),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let template = Template::parse("{{x}} {name:10}|{size:>8}{depth:^3}}}").unwrap();
+        assert_eq!(template.parts, [
+            Part::Literal("{x} ".to_string()),
+            Part::Field { field: Field::Name, align: None, width: 10 },
+            Part::Literal("|".to_string()),
+            Part::Field { field: Field::Size, align: Some(Align::Right), width: 8 },
+            Part::Field { field: Field::Depth, align: Some(Align::Center), width: 3 },
+            Part::Literal("}".to_string()),
+        ]);
+        assert!(template.needs_metadata());
+        assert!(!Template::parse("{name}").unwrap().needs_metadata());
+        assert_eq!(Template::parse("{md5} {sha256} {md5}").unwrap().algorithms(), [Algorithm::Md5, Algorithm::Sha256]);
+        assert_eq!(Template::parse("{nope}").unwrap_err(), Error::UnknownPlaceholder("nope".to_string()));
+        assert_eq!(Template::parse("{size:x}").unwrap_err(), Error::InvalidSpec("x".to_string()));
+        assert_eq!(Template::parse("{name").unwrap_err(), Error::UnmatchedBrace('{'));
+        assert_eq!(Template::parse("a}b").unwrap_err(), Error::UnmatchedBrace('}'));
+    }
+
+    #[test]
+    fn render() {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        fs::write(root.path().join("f"), "hello").unwrap();
+        let entry = bfind::WalkBuilder::new(root.path()).build().unwrap().next().unwrap().unwrap();
+        let template = Template::parse("{name:3}|{size:4}|{size:<3}|{type:^6}|{depth}").unwrap();
+        let mut out = String::new();
+        template.render(&mut out, &entry, Path::new("f"), entry.metadata().ok(), &Digests::default());
+        assert_eq!(out, "f  |   5|5  | file |1");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn render_os() {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::ffi::OsStringExt;
+
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        let name = std::ffi::OsStr::from_bytes(b"f\xff");
+        fs::write(root.path().join(name), "hello").unwrap();
+        let entry = bfind::WalkBuilder::new(root.path()).build().unwrap().next().unwrap().unwrap();
+        let template = Template::parse("--in={name} {size}").unwrap();
+        let arg = template.render_os(&entry, Path::new(name), entry.metadata().ok(), &Digests::default());
+        assert_eq!(arg.into_vec(), b"--in=f\xff 5");
+    }
+}
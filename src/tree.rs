@@ -0,0 +1,160 @@
+//! The `--tree` output: the entries are collected, then drawn as an indented tree under each starting directory,
+//! sorted by name.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use bfind::DirEntry;
+
+use crate::color::Colors;
+use crate::long::human_size;
+
+#[derive(Default)]
+struct Node {
+    /// The entry, unless it was not yielded, like a directory whose entries were but which was filtered out itself.
+    entry:      Option<DirEntry>,
+    size:       u64,
+    children:   BTreeMap<OsString, Node>,
+
+    /// Number of entries and total size below the node, filled in before drawing.
+    total:      (u64, u64),
+}
+
+impl Node {
+    fn sum_totals(&mut self) -> (u64, u64) {
+        let mut total = (0, 0);
+        for child in self.children.values_mut() {
+            let (count, size) = child.sum_totals();
+            total.0 += 1 + count;
+            total.1 += child.size + size;
+        }
+        self.total = total;
+        total
+    }
+}
+
+pub struct Tree {
+    roots:      Vec<(PathBuf, Node)>,
+
+    /// Print the number of entries and their total size after each directory.
+    totals:     bool,
+}
+
+impl Tree {
+    pub fn new<I: IntoIterator<Item = PathBuf>>(roots: I, totals: bool) -> Self {
+        Self { roots: roots.into_iter().map(|root| (root, Node::default())).collect(), totals }
+    }
+
+    pub fn needs_metadata(&self) -> bool {
+        self.totals
+    }
+
+    pub fn insert(&mut self, entry: DirEntry) {
+        let (root, node) = &mut self.roots[entry.root_index()];
+        let Ok(relative) = entry.path().strip_prefix(root.as_path()) else {
+            return;
+        };
+        let mut node = node;
+        for name in relative.iter() {
+            node = node.children.entry(name.to_os_string()).or_default();
+        }
+        if self.totals && !entry.file_type().is_dir() {
+            node.size = entry.metadata().map_or(0, |m| m.len());
+        }
+        node.entry = Some(entry);
+    }
+
+    /// Draws the tree, with the names colored by `colors`.
+    pub fn render(&mut self, colors: Option<&Colors>) -> String {
+        let mut out = String::new();
+        let totals = self.totals;
+        for (root, node) in &mut self.roots {
+            node.sum_totals();
+            write!(out, "{}", root.display()).unwrap();
+            if totals {
+                push_totals(&mut out, node.total);
+            }
+            out.push('\n');
+            render_children(&mut out, node, &mut String::new(), totals, colors);
+        }
+        out
+    }
+}
+
+fn push_totals(out: &mut String, (count, size): (u64, u64)) {
+    let entries = if count == 1 { "entry" } else { "entries" };
+    write!(out, " ({} {}, {})", count, entries, human_size(size)).unwrap();
+}
+
+/// Draws the children of `node`, each line starting with `indent`.
+fn render_children(out: &mut String, node: &Node, indent: &mut String, totals: bool, colors: Option<&Colors>) {
+    let last = node.children.len().saturating_sub(1);
+    for (i, (name, child)) in node.children.iter().enumerate() {
+        out.push_str(indent);
+        out.push_str(if i == last { "└── " } else { "├── " });
+        match (colors, &child.entry) {
+            (Some(colors), Some(entry)) => colors.paint(out, entry, Path::new(name), entry.metadata().ok()),
+            _ => write!(out, "{}", Path::new(name).display()).unwrap(),
+        }
+        let is_dir = child.entry.as_ref().is_none_or(|entry| entry.file_type().is_dir());
+        if totals && is_dir {
+            push_totals(out, child.total);
+        }
+        out.push('\n');
+        if !child.children.is_empty() {
+            let len = indent.len();
+            indent.push_str(if i == last { "    " } else { "│   " });
+            render_children(out, child, indent, totals, colors);
+            indent.truncate(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn tree(totals: bool, max_depth: usize) -> String {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        for dir in ["a/b/c", "d"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+        }
+        for (file, len) in [("f", 2000), ("a/g", 10), ("a/b/h", 1), ("a/b/c/i", 0)] {
+            fs::write(root.path().join(file), vec![0; len]).unwrap();
+        }
+        let walker = bfind::WalkBuilder::new(root.path()).max_depth(max_depth).build().unwrap();
+        let mut tree = Tree::new([root.path().to_path_buf()], totals);
+        for entry in walker {
+            tree.insert(entry.unwrap());
+        }
+        let out = tree.render(None);
+        out.replacen(&root.path().display().to_string(), "root", 1)
+    }
+
+    #[test]
+    fn draw() {
+        assert_eq!(tree(false, usize::MAX), "\
+root
+├── a
+│   ├── b
+│   │   ├── c
+│   │   │   └── i
+│   │   └── h
+│   └── g
+├── d
+└── f
+");
+        assert_eq!(tree(true, 2), "\
+root (5 entries, 2.0K)
+├── a (2 entries, 10)
+│   ├── b (0 entries, 0)
+│   └── g
+├── d (0 entries, 0)
+└── f
+");
+    }
+}
@@ -0,0 +1,131 @@
+//! Names of users and groups, looked up once per ID.
+
+use std::collections::HashMap;
+
+/// Caches the names of users and groups by ID. IDs without a name are printed as numbers.
+#[derive(Debug, Default)]
+pub struct Users {
+    /// Names by ID, and whether the ID has a name rather than its number.
+    users:      HashMap<u32, (String, bool)>,
+    groups:     HashMap<u32, (String, bool)>,
+}
+
+impl Users {
+    fn user(&mut self, uid: u32) -> &(String, bool) {
+        self.users.entry(uid).or_insert_with(|| lookup_user(uid).map_or_else(|| (uid.to_string(), false), |name| (name, true)))
+    }
+
+    fn group(&mut self, gid: u32) -> &(String, bool) {
+        self.groups.entry(gid).or_insert_with(|| lookup_group(gid).map_or_else(|| (gid.to_string(), false), |name| (name, true)))
+    }
+
+    pub fn user_name(&mut self, uid: u32) -> &str {
+        &self.user(uid).0
+    }
+
+    pub fn group_name(&mut self, gid: u32) -> &str {
+        &self.group(gid).0
+    }
+
+    /// Whether a user with this ID exists, for `nouser`.
+    pub fn has_user(&mut self, uid: u32) -> bool {
+        self.user(uid).1
+    }
+
+    /// Whether a group with this ID exists, for `nogroup`.
+    pub fn has_group(&mut self, gid: u32) -> bool {
+        self.group(gid).1
+    }
+}
+
+/// Calls a reentrant lookup function from the getpwuid_r family with a growing buffer, returning what `get` takes
+/// from the entry it found.
+#[cfg(unix)]
+fn lookup<T, F, R>(f: F, get: fn(&T) -> R) -> Option<R>
+where
+    F: Fn(*mut T, *mut libc::c_char, usize, *mut *mut T) -> libc::c_int,
+{
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut entry = std::mem::MaybeUninit::<T>::uninit();
+        let mut result = std::ptr::null_mut();
+        match f(entry.as_mut_ptr(), buf.as_mut_ptr(), buf.len(), &mut result) {
+            libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            // SAFETY: the lookup succeeded, so it filled in `entry`, whose strings point into `buf`.
+            0 if !result.is_null() => return Some(get(unsafe { &*result })),
+            _ => return None,
+        }
+    }
+}
+
+/// Copies a name out of a passwd or group entry.
+#[cfg(unix)]
+fn name(ptr: *const libc::c_char) -> String {
+    // SAFETY: the pointer comes from an entry filled in by `lookup`, while its buffer is alive.
+    unsafe { std::ffi::CStr::from_ptr(ptr) }.to_string_lossy().into_owned()
+}
+
+#[cfg(unix)]
+fn lookup_user(uid: u32) -> Option<String> {
+    // SAFETY: the pointers come from `lookup` and are valid for the given lengths.
+    lookup(|pwd, buf, len, result| unsafe { libc::getpwuid_r(uid, pwd, buf, len, result) }, |pwd: &libc::passwd| name(pwd.pw_name))
+}
+
+#[cfg(unix)]
+fn lookup_group(gid: u32) -> Option<String> {
+    // SAFETY: the pointers come from `lookup` and are valid for the given lengths.
+    lookup(|grp, buf, len, result| unsafe { libc::getgrgid_r(gid, grp, buf, len, result) }, |grp: &libc::group| name(grp.gr_name))
+}
+
+/// The ID of the user with this name, for `user is`.
+#[cfg(unix)]
+pub fn user_id(user: &str) -> Option<u32> {
+    let user = std::ffi::CString::new(user).ok()?;
+    // SAFETY: the pointers come from `lookup` and are valid for the given lengths.
+    lookup(|pwd, buf, len, result| unsafe { libc::getpwnam_r(user.as_ptr(), pwd, buf, len, result) }, |pwd: &libc::passwd| pwd.pw_uid)
+}
+
+/// The ID of the group with this name, for `group is`.
+#[cfg(unix)]
+pub fn group_id(group: &str) -> Option<u32> {
+    let group = std::ffi::CString::new(group).ok()?;
+    // SAFETY: the pointers come from `lookup` and are valid for the given lengths.
+    lookup(|grp, buf, len, result| unsafe { libc::getgrnam_r(group.as_ptr(), grp, buf, len, result) }, |grp: &libc::group| grp.gr_gid)
+}
+
+#[cfg(not(unix))]
+fn lookup_user(_uid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(not(unix))]
+fn lookup_group(_gid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(not(unix))]
+pub fn user_id(_user: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(not(unix))]
+pub fn group_id(_group: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_and_unknown() {
+        let mut users = Users::default();
+        assert_eq!(users.user_name(0), "root");
+        assert_eq!(users.user_name(u32::MAX - 1), (u32::MAX - 1).to_string());
+        assert!(!users.group_name(0).is_empty());
+        assert!(users.has_user(0) && !users.has_user(u32::MAX - 1));
+        assert_eq!(user_id("root"), Some(0));
+        assert_eq!(group_id(users.group_name(0)), Some(0));
+        assert_eq!(user_id("no such user"), None);
+    }
+}
@@ -0,0 +1,36 @@
+use crate::walk::DirEntry;
+use crate::walk::Error;
+
+/// What the traversal should do after visiting an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    /// Go on, descending into the entry if it is a directory.
+    Continue,
+
+    /// Go on, but do not descend into the entry. Same as `Continue` for entries that are not directories.
+    Skip,
+
+    /// Stop the whole traversal as soon as possible. Other worker threads may still visit a few entries.
+    Quit,
+}
+
+/// Receives the entries of a traversal started by [`WalkBuilder::visit`](crate::WalkBuilder::visit).
+///
+/// The methods are called concurrently from all worker threads, in approximately breadth-first order.
+pub trait Visitor: Send + Sync {
+    fn visit(&self, entry: &DirEntry) -> Visit;
+
+    /// Called for errors reading a directory or an entry. The traversal goes on by default.
+    fn error(&self, _err: Error) -> Visit {
+        Visit::Continue
+    }
+}
+
+impl<F> Visitor for F
+where
+    F: Fn(&DirEntry) -> Visit + Send + Sync,
+{
+    fn visit(&self, entry: &DirEntry) -> Visit {
+        self(entry)
+    }
+}
@@ -0,0 +1,1483 @@
+#[cfg(unix)]
+use std::os::unix::fs::DirEntryExt;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::any::Any;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::SyncSender;
+use std::collections::HashSet;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+use tempfile::NamedTempFile;
+use thiserror::Error;
+
+use crate::checkpoint;
+use crate::deque;
+use crate::path_queue;
+use crate::path_queue::Compression;
+use crate::path_queue::EntryReader;
+use crate::path_queue::EntryWriter;
+use crate::path_queue::MemoryBudget;
+use crate::path_queue::PathQueue;
+use crate::path_queue::QueueEntry;
+use crate::path_queue::SpillOptions;
+use crate::visit::Visit;
+use crate::visit::Visitor;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: io::Error
+    },
+
+    #[error("{}: file system loop detected", path.display())]
+    Loop {
+        path: PathBuf
+    },
+
+    #[error("{}: not a valid checkpoint", path.display())]
+    InvalidCheckpoint {
+        path: PathBuf
+    },
+
+    #[error("{}: checkpoint was taken with different starting points", path.display())]
+    CheckpointMismatch {
+        path: PathBuf
+    },
+
+    #[error("{}: cannot save a checkpoint, {lost} queued directories were dropped by a failed worker thread", path.display())]
+    CheckpointIncomplete {
+        path: PathBuf,
+        lost: usize
+    },
+
+    #[error("path_queue::Error: {source}")]
+    PathQueue {
+        #[from]
+        source: path_queue::Error
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Total number of entries the in-memory ring buffers of all queues can hold before spilling to disk.
+const QUEUE_CAPACITY: usize = 1024 * 512;
+
+/// Number of entries in the work-stealing deque of each worker, from which other workers steal.
+const DEQUE_CAPACITY: usize = 256;
+
+/// Share of a memory budget given to the ring buffers. The rest is left for the paths in them.
+const RING_SHARE: u64 = 2;
+
+/// Number of entries the workers may produce ahead of the consumer of a [`BfsWalker`].
+const CHANNEL_CAPACITY: usize = 4096;
+
+/// An entry found by a [`BfsWalker`].
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    path:           PathBuf,
+    depth:          usize,
+    root:           usize,
+    dev:            u64,
+    ino:            u64,
+    file_type:      fs::FileType,
+    follow_link:    bool,
+    is_link:        bool,
+    metadata:       OnceLock<fs::Metadata>,
+    link_target:    OnceLock<PathBuf>,
+    extension:      OnceLock<Arc<dyn Any + Send + Sync>>,
+}
+
+impl DirEntry {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn into_path(self) -> PathBuf {
+        self.path
+    }
+
+    pub fn file_name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or(self.path.as_os_str())
+    }
+
+    /// The type of the entry. Symbolic links are resolved if the walker follows them.
+    pub fn file_type(&self) -> fs::FileType {
+        self.file_type
+    }
+
+    /// The number of directories between the entry and the starting directory it was found in. Entries directly in
+    /// a starting directory have depth 1.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The index of the starting directory the entry was found in, in the order they were given to the builder.
+    pub fn root_index(&self) -> usize {
+        self.root
+    }
+
+    /// The inode number of the entry, or of its target if it is a followed symbolic link. Always 0 on platforms
+    /// without inodes.
+    pub fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    /// The device of the file system the entry is on. It is only looked up for directories when the walker stays on
+    /// one file system or follows symbolic links; otherwise it is the device of the parent directory.
+    pub fn dev(&self) -> u64 {
+        self.dev
+    }
+
+    /// The metadata of the entry, following symbolic links if the walker follows them. It is fetched on the first
+    /// call and cached afterwards.
+    pub fn metadata(&self) -> io::Result<&fs::Metadata> {
+        if let Some(metadata) = self.metadata.get() {
+            return Ok(metadata);
+        }
+        let metadata = if self.follow_link {
+            fs::metadata(&self.path)?
+        } else {
+            fs::symlink_metadata(&self.path)?
+        };
+        Ok(self.metadata.get_or_init(|| metadata))
+    }
+
+    /// Whether the entry itself is a symbolic link, even if the walker follows it.
+    pub fn is_symlink(&self) -> bool {
+        self.is_link
+    }
+
+    /// The target of a symbolic link. It is read on the first call and cached afterwards.
+    pub fn read_link(&self) -> io::Result<&Path> {
+        if let Some(target) = self.link_target.get() {
+            return Ok(target);
+        }
+        let target = fs::read_link(&self.path)?;
+        Ok(self.link_target.get_or_init(|| target))
+    }
+
+    /// Data attached to the entry by the caller, like a value computed by a filter in a worker thread and read by the
+    /// consumer of the walker. It is computed with `init` on the first call and cached afterwards. Returns `None` if
+    /// a value of another type was attached already.
+    pub fn extension<T: Any + Send + Sync>(&self, init: impl FnOnce() -> T) -> Option<&T> {
+        self.extension.get_or_init(|| Arc::new(init())).downcast_ref()
+    }
+}
+
+type Filter = Box<dyn Fn(&DirEntry) -> bool + Send + Sync>;
+
+struct WalkOptions {
+    allow_hidden:       bool,
+    follow_links:       bool,
+    max_depth:          usize,
+    same_file_system:   bool,
+    ignores:            Vec<OsString>,
+    threads:            usize,
+    prefetch:           bool,
+    spill:              SpillOptions,
+}
+
+impl WalkOptions {
+    fn is_skipped(&self, file_name: &OsStr) -> bool {
+        (!self.allow_hidden && file_name.as_encoded_bytes().starts_with(b"."))
+            || self.ignores.iter().any(|item| item == file_name)
+    }
+}
+
+/// Configures and starts a parallel breadth-first traversal.
+///
+/// ```no_run
+/// let walker = bfind::WalkBuilder::new(".")
+///     .hidden(true)
+///     .ignore(["target"])
+///     .build()?;
+/// for entry in walker {
+///     println!("{}", entry?.path().display());
+/// }
+/// # Ok::<(), bfind::Error>(())
+/// ```
+pub struct WalkBuilder {
+    roots:      Vec<PathBuf>,
+    opts:       WalkOptions,
+    filters:    Vec<Filter>,
+    resume:     Option<PathBuf>,
+}
+
+impl WalkBuilder {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            roots: vec![root.as_ref().to_path_buf()],
+            opts: WalkOptions {
+                allow_hidden: false,
+                follow_links: false,
+                max_depth: usize::MAX,
+                same_file_system: false,
+                ignores: Vec::new(),
+                threads: 0,
+                prefetch: false,
+                spill: SpillOptions::default(),
+            },
+            filters: Vec::new(),
+            resume: None,
+        }
+    }
+
+    pub fn add_root<P: AsRef<Path>>(mut self, root: P) -> Self {
+        self.roots.push(root.as_ref().to_path_buf());
+        self
+    }
+
+    /// Include entries whose names start with a dot. Defaults to `false`.
+    pub fn hidden(mut self, yes: bool) -> Self {
+        self.opts.allow_hidden = yes;
+        self
+    }
+
+    /// Follow symbolic links. Defaults to `false`.
+    pub fn follow_links(mut self, yes: bool) -> Self {
+        self.opts.follow_links = yes;
+        self
+    }
+
+    /// Do not descend more than `depth` levels below the starting directories, so that the deepest entries yielded
+    /// have depth `depth`. Defaults to no limit.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.opts.max_depth = depth;
+        self
+    }
+
+    /// Do not descend into directories on other file systems than their starting directory. Defaults to `false`.
+    pub fn same_file_system(mut self, yes: bool) -> Self {
+        self.opts.same_file_system = yes;
+        self
+    }
+
+    /// Skip entries with any of these names, and do not descend into them.
+    pub fn ignore<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.opts.ignores.extend(names.into_iter().map(|name| name.as_ref().to_os_string()));
+        self
+    }
+
+    /// Number of worker threads. Defaults to the available parallelism plus one.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.opts.threads = threads;
+        self
+    }
+
+    /// Read the metadata of the entries that pass the filters, and the targets of symbolic links, in the worker
+    /// threads, so that [`DirEntry::metadata`] and [`DirEntry::read_link`] return cached values. Defaults to `false`.
+    pub fn prefetch_metadata(mut self, yes: bool) -> Self {
+        self.opts.prefetch = yes;
+        self
+    }
+
+    /// Spill pending directories to disk when there are too many to keep in memory. Defaults to `true`; if
+    /// disabled, they are all kept in memory.
+    pub fn spill(mut self, yes: bool) -> Self {
+        self.opts.spill.disabled = !yes;
+        self
+    }
+
+    /// Directory for spill files. Defaults to the system temporary directory.
+    pub fn spill_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.opts.spill.dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Fail the traversal with [`path_queue::Error::SpillLimitExceeded`] instead of writing more than `bytes` to
+    /// spill files in total. Defaults to no limit.
+    pub fn max_spill(mut self, bytes: u64) -> Self {
+        self.opts.spill.max_size = Some(bytes);
+        self
+    }
+
+    /// How directories spilled to disk are compressed. Defaults to LZ4 if it is compiled in.
+    pub fn spill_compression(mut self, compression: Compression) -> Self {
+        self.opts.spill.compression = compression;
+        self
+    }
+
+    /// Limit the memory used by the directories waiting to be read, including their paths, spilling the rest to
+    /// disk. The ring buffers of the queues are sized to take half of the budget. The budget can be cloned before
+    /// to read the peak usage afterwards. Defaults to an unlimited budget sized for 512K entries.
+    pub fn memory_budget(mut self, budget: MemoryBudget) -> Self {
+        self.opts.spill.memory = budget;
+        self
+    }
+
+    /// Continue the traversal saved by [`Paused::checkpoint`] instead of starting from the roots, which must be the
+    /// same as when the checkpoint was taken. Entries yielded before the checkpoint are not yielded again, as long as
+    /// the other options are the same too.
+    pub fn resume<P: AsRef<Path>>(mut self, checkpoint: P) -> Self {
+        self.resume = Some(checkpoint.as_ref().to_path_buf());
+        self
+    }
+
+    /// Only yield entries for which `filter` returns `true`. Filters run in the worker threads and do not prevent
+    /// descending into directories.
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&DirEntry) -> bool + Send + Sync + 'static,
+    {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Starts the traversal in the background and returns an iterator over the entries.
+    pub fn build(self) -> Result<BfsWalker> {
+        let (tx, rx) = mpsc::sync_channel(CHANNEL_CAPACITY);
+        let (shared, locals) = self.into_shared()?;
+        let shared = Arc::new(shared);
+        let visitor = Arc::new(ChannelVisitor { tx: tx.clone() });
+        let handles = locals.into_iter().enumerate().map(|(i, mut local)| {
+            let shared = shared.clone();
+            let visitor = visitor.clone();
+            let tx = tx.clone();
+            thread::spawn(move || {
+                let result = breadth_first_traverse(&shared, i, &mut local, &*visitor);
+                shared.pause.exit(&local);
+                if let Err(e) = result {
+                    shared.quit.store(true, Ordering::Release);
+                    let _ = tx.send(Err(e));
+                }
+            })
+        }).collect();
+        Ok(BfsWalker { shared, rx: Some(rx), handles })
+    }
+
+    /// Runs the traversal, calling `visitor` from the worker threads for each entry, and returns when it is done.
+    ///
+    /// ```no_run
+    /// use bfind::Visit;
+    /// bfind::WalkBuilder::new(".").visit(|entry: &bfind::DirEntry| {
+    ///     if entry.file_name() == "node_modules" {
+    ///         Visit::Skip
+    ///     } else {
+    ///         println!("{}", entry.path().display());
+    ///         Visit::Continue
+    ///     }
+    /// })?;
+    /// # Ok::<(), bfind::Error>(())
+    /// ```
+    pub fn visit<V: Visitor>(self, visitor: V) -> Result<()> {
+        let (shared, locals) = self.into_shared()?;
+        thread::scope(|s| {
+            let handles: Vec<_> = locals.into_iter().enumerate().map(|(i, mut local)| {
+                let shared = &shared;
+                let visitor = &visitor;
+                s.spawn(move || {
+                    let result = breadth_first_traverse(shared, i, &mut local, visitor);
+                    shared.pause.exit(&local);
+                    result.inspect_err(|_| shared.quit.store(true, Ordering::Release))
+                })
+            }).collect();
+            handles.into_iter().try_for_each(|handle| handle.join().expect("worker thread panicked"))
+        })
+    }
+
+    fn into_shared(mut self) -> Result<(Shared, Vec<LocalQueue>)> {
+        if self.opts.threads == 0 {
+            self.opts.threads = thread::available_parallelism().map_or(1, |n| n.get()) + 1;
+        }
+        let num_threads = self.opts.threads;
+
+        // Each worker has a deque and an overflow queue with two ring buffers of `len` entries.
+        let mut deque_len = DEQUE_CAPACITY;
+        let mut len = QUEUE_CAPACITY / num_threads;
+        let limit = self.opts.spill.memory.limit();
+        if limit != u64::MAX {
+            let slots = (limit / RING_SHARE / (num_threads * size_of::<QueueEntry>()) as u64) as usize;
+            // Ring buffers are rounded up to a power of two, so round down to stay within the budget.
+            deque_len = 1 << deque_len.min(slots / 4).max(1).ilog2();
+            len = 1 << len.min(slots.saturating_sub(deque_len) / 2).max(1).ilog2();
+        }
+
+        let mut stealers = Vec::with_capacity(num_threads);
+        let mut locals = Vec::with_capacity(num_threads);
+        for _ in 0..num_threads {
+            let (deque, stealer) = deque::new(deque_len);
+            let overflow = PathQueue::with_spill_options(len as u32, len as u32, self.opts.spill.clone())?;
+            stealers.push(stealer);
+            locals.push(LocalQueue::new(deque, overflow, self.opts.spill.memory.clone()));
+        }
+
+        let mut counter = 0;
+        let mut yielded = 0;
+        let mut root_devs = Vec::with_capacity(self.roots.len());
+        let mut visited = HashSet::new();
+        for path in &self.roots {
+            root_devs.push(fs::metadata(path).map_or(0, |m| dev_ino(&m).0));
+        }
+        if let Some(checkpoint) = &self.resume {
+            let invalid = || Error::InvalidCheckpoint { path: checkpoint.clone() };
+            let file = File::open(checkpoint).map_err(|source| Error::Io { path: checkpoint.clone(), source })?;
+            let mut reader = BufReader::new(file);
+            let header = checkpoint::read_header(&mut reader).map_err(|source| match source.kind() {
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => invalid(),
+                _ => Error::Io { path: checkpoint.clone(), source },
+            })?;
+            if header.roots != self.roots {
+                return Err(Error::CheckpointMismatch { path: checkpoint.clone() });
+            }
+            yielded = header.yielded;
+            visited.extend(header.visited);
+            for (i, entry) in EntryReader::new(reader).enumerate() {
+                locals[i % num_threads].push(entry.map_err(|_| invalid())?)?;
+                counter += 1;
+            }
+        } else {
+            for (root, path) in self.roots.iter().enumerate() {
+                let (dev, ino) = fs::metadata(path).map_or((0, 0), |m| dev_ino(&m));
+                if !self.opts.follow_links && path.is_symlink() {
+                    continue;
+                }
+                if let Some(file_name) = path.file_name() {
+                    if self.opts.is_skipped(file_name) {
+                        continue;
+                    }
+                }
+                if self.opts.follow_links && !visited.insert((dev, ino)) {
+                    continue;
+                }
+                locals[0].push(QueueEntry {
+                    path: path.clone(),
+                    depth: 0,
+                    root: root as u32,
+                    dev,
+                    ino,
+                    kind: path_queue::FileKind::Dir,
+                })?;
+                counter += 1;
+            }
+        }
+
+        let shared = Shared {
+            roots: self.roots,
+            root_devs,
+            visited: Mutex::new(visited),
+            opts: self.opts,
+            filters: self.filters,
+            stealers,
+            counter: AtomicUsize::new(counter),
+            quit: AtomicBool::new(false),
+            pause: Pause::new(num_threads),
+            yielded: AtomicU64::new(yielded),
+            dirs_read: AtomicU64::new(0),
+            entries_seen: AtomicU64::new(0),
+            reading: AtomicUsize::new(0),
+            depth: AtomicUsize::new(0),
+            max_depth: AtomicUsize::new(0),
+            steals: AtomicU64::new(0),
+            times: (0..num_threads).map(|_| ThreadTimes::default()).collect(),
+        };
+        Ok((shared, locals))
+    }
+}
+
+struct Shared {
+    roots:      Vec<PathBuf>,
+    root_devs:  Vec<u64>,
+
+    /// Device and inode numbers of the directories queued so far, to detect loops when following symbolic links.
+    visited:    Mutex<HashSet<(u64, u64)>>,
+
+    opts:       WalkOptions,
+    filters:    Vec<Filter>,
+    stealers:   Vec<deque::Stealer<QueueEntry>>,
+
+    /// Number of directories queued or being read. The traversal is done when it drops to zero.
+    counter:    AtomicUsize,
+
+    quit:       AtomicBool,
+    pause:      Pause,
+
+    /// Number of entries yielded by the [`BfsWalker`], starting from the number in the checkpoint the traversal was
+    /// resumed from.
+    yielded:    AtomicU64,
+
+    dirs_read:  AtomicU64,
+    entries_seen: AtomicU64,
+
+    /// Number of directories being read, which `counter` includes.
+    reading:    AtomicUsize,
+
+    /// Depth of the deepest directory read so far.
+    depth:      AtomicUsize,
+
+    /// Depth of the deepest entry found so far.
+    max_depth:  AtomicUsize,
+
+    steals:     AtomicU64,
+    times:      Vec<ThreadTimes>,
+}
+
+/// Time a worker thread spent reading directories and waiting for some, in nanoseconds.
+#[derive(Default)]
+struct ThreadTimes {
+    working:    AtomicU64,
+    sleeping:   AtomicU64,
+}
+
+impl ThreadTimes {
+    /// Adds the time since `since` to `counter` and resets `since`.
+    fn add(counter: &AtomicU64, since: &mut Instant) {
+        let now = Instant::now();
+        counter.fetch_add((now - *since).as_nanos() as u64, Ordering::Relaxed);
+        *since = now;
+    }
+}
+
+type CheckpointWriter = EntryWriter<BufWriter<NamedTempFile>>;
+
+/// Stops the workers between two directories, where every directory is either done or queued, so that the queues
+/// can be saved.
+struct Pause {
+    requested:  AtomicBool,
+    state:      Mutex<PauseState>,
+    cond:       Condvar,
+}
+
+struct PauseState {
+    requested:  bool,
+
+    /// Workers that have not exited.
+    running:    usize,
+
+    paused:     usize,
+
+    /// Set while the paused workers write their queues to a checkpoint, one at a time.
+    writer:     Option<CheckpointWriter>,
+
+    /// Workers that have written their queues to `writer`.
+    written:    usize,
+    error:      Option<Error>,
+
+    /// Directories dropped by workers that exited with a nonempty queue, which a checkpoint cannot save.
+    lost:       usize,
+}
+
+impl Pause {
+    fn new(threads: usize) -> Self {
+        Self {
+            requested: AtomicBool::new(false),
+            state: Mutex::new(PauseState {
+                requested: false,
+                running: threads,
+                paused: 0,
+                writer: None,
+                written: 0,
+                error: None,
+                lost: 0,
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Called by a worker between directories. Returns when the pause is over.
+    fn wait(&self, local: &mut LocalQueue) {
+        let mut state = self.state.lock().unwrap();
+        state.paused += 1;
+        self.cond.notify_all();
+        let mut written = false;
+        while state.requested {
+            if !written {
+                if let Some(writer) = &mut state.writer {
+                    if let Err(e) = local.write_to(writer) {
+                        state.error.get_or_insert(e);
+                    }
+                    state.written += 1;
+                    written = true;
+                    self.cond.notify_all();
+                    continue;
+                }
+            }
+            state = self.cond.wait(state).unwrap();
+        }
+        state.paused -= 1;
+    }
+
+    /// Called by a worker when it exits, with the directories left in its queue if it stopped on an error.
+    fn exit(&self, local: &LocalQueue) {
+        let mut state = self.state.lock().unwrap();
+        state.running -= 1;
+        state.lost += local.len();
+        self.cond.notify_all();
+    }
+}
+
+/// A running traversal, yielding entries in approximately breadth-first order as the worker threads find them.
+///
+/// Dropping the walker stops the worker threads.
+pub struct BfsWalker {
+    shared:     Arc<Shared>,
+    rx:         Option<Receiver<Result<DirEntry>>>,
+    handles:    Vec<JoinHandle<()>>,
+}
+
+/// The state of a traversal at some point, from [`Monitor::progress`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Directories read so far.
+    pub dirs_read:      u64,
+
+    /// Entries found in the directories read so far, before filtering.
+    pub entries_seen:   u64,
+
+    /// Entries yielded by the walker, including those yielded before the checkpoint the traversal was resumed from.
+    pub yielded:        u64,
+
+    /// Depth of the deepest directory read so far, relative to the roots.
+    pub depth:          usize,
+
+    /// Directories waiting to be read.
+    pub queued:         usize,
+
+    /// Directories waiting to be read in spill files, which `queued` includes.
+    pub spilled:        u64,
+}
+
+/// Statistics of a traversal, from [`Monitor::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub progress:       Progress,
+
+    /// Depth of the deepest entry found, relative to the roots.
+    pub max_depth:      usize,
+
+    /// Number of times a worker thread stole directories from another.
+    pub steals:         u64,
+
+    pub spill_bytes_written: u64,
+    pub spill_bytes_read: u64,
+
+    /// Most memory the queues held at once, as counted by the [`MemoryBudget`].
+    pub peak_memory:    u64,
+
+    /// Time each worker thread spent reading directories and, separately, waiting for directories to read.
+    pub threads:        Vec<(Duration, Duration)>,
+}
+
+/// Reports the [`Progress`] of a traversal from any thread.
+#[derive(Clone)]
+pub struct Monitor {
+    shared:     Arc<Shared>,
+}
+
+impl Monitor {
+    pub fn progress(&self) -> Progress {
+        let shared = &self.shared;
+        // The counters are read separately, so this is only a snapshot if the traversal is paused or done.
+        let reading = shared.reading.load(Ordering::Acquire);
+        let queued = shared.counter.load(Ordering::Acquire).saturating_sub(reading);
+        Progress {
+            dirs_read: shared.dirs_read.load(Ordering::Relaxed),
+            entries_seen: shared.entries_seen.load(Ordering::Relaxed),
+            yielded: shared.yielded.load(Ordering::Relaxed),
+            depth: shared.depth.load(Ordering::Relaxed),
+            queued,
+            spilled: shared.opts.spill.entries_spilled(),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let shared = &self.shared;
+        let nanos = |counter: &AtomicU64| Duration::from_nanos(counter.load(Ordering::Relaxed));
+        Stats {
+            progress: self.progress(),
+            max_depth: shared.max_depth.load(Ordering::Relaxed),
+            steals: shared.steals.load(Ordering::Relaxed),
+            spill_bytes_written: shared.opts.spill.bytes_written(),
+            spill_bytes_read: shared.opts.spill.bytes_read(),
+            peak_memory: shared.opts.spill.memory.peak(),
+            threads: shared.times.iter().map(|t| (nanos(&t.working), nanos(&t.sleeping))).collect(),
+        }
+    }
+}
+
+impl BfsWalker {
+    /// Number of entries yielded so far, including those yielded before the checkpoint the traversal was resumed
+    /// from.
+    pub fn yielded(&self) -> u64 {
+        self.shared.yielded.load(Ordering::Relaxed)
+    }
+
+    /// Returns a handle to watch the progress of the traversal, which may be sent to another thread.
+    pub fn monitor(&self) -> Monitor {
+        Monitor { shared: self.shared.clone() }
+    }
+
+    /// Stops the worker threads once they are done with the directories they are reading. The entries found before
+    /// that and not yielded yet are passed to `pending`. The workers go on when the returned guard is dropped.
+    pub fn pause<F: FnMut(Result<DirEntry>)>(&mut self, mut pending: F) -> Paused<'_> {
+        let pause = &self.shared.pause;
+        pause.state.lock().unwrap().requested = true;
+        pause.requested.store(true, Ordering::Release);
+        let mut pass = |entry: Result<DirEntry>| {
+            if entry.is_ok() {
+                self.shared.yielded.fetch_add(1, Ordering::Relaxed);
+            }
+            pending(entry);
+        };
+        loop {
+            {
+                let state = pause.state.lock().unwrap();
+                if state.paused == state.running {
+                    break;
+                }
+            }
+            // Keep draining the channel, as workers may be blocked sending to it.
+            match self.rx.as_ref().map(|rx| rx.recv_timeout(Duration::from_millis(10))) {
+                Some(Ok(entry)) => pass(entry),
+                Some(Err(mpsc::RecvTimeoutError::Timeout)) => {},
+                _ => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+        // Workers send nothing while paused, but what they sent before is still in the channel.
+        if let Some(rx) = &self.rx {
+            while let Ok(entry) = rx.try_recv() {
+                pass(entry);
+            }
+        }
+        Paused { walker: self }
+    }
+}
+
+/// A paused [`BfsWalker`]. Dropping it lets the traversal go on.
+pub struct Paused<'a> {
+    walker: &'a mut BfsWalker,
+}
+
+impl Paused<'_> {
+    /// Saves the directories waiting to be read, so that [`WalkBuilder::resume`] can continue the traversal. The file
+    /// is replaced atomically.
+    pub fn checkpoint<P: AsRef<Path>>(self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let io_error = |source| Error::Io { path: path.to_path_buf(), source };
+        let shared = &self.walker.shared;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut file = BufWriter::new(NamedTempFile::new_in(dir).map_err(io_error)?);
+        let header = checkpoint::Header {
+            yielded: self.walker.yielded(),
+            roots: shared.roots.clone(),
+            visited: shared.visited.lock().unwrap().iter().copied().collect(),
+        };
+        checkpoint::write_header(&mut file, &header).map_err(io_error)?;
+        let writer = EntryWriter::new(file, shared.opts.spill.compression)?;
+
+        let pause = &shared.pause;
+        let mut state = pause.state.lock().unwrap();
+        if state.lost > 0 {
+            return Err(Error::CheckpointIncomplete { path: path.to_path_buf(), lost: state.lost });
+        }
+        state.writer = Some(writer);
+        state.written = 0;
+        pause.cond.notify_all();
+        while state.written < state.paused {
+            state = pause.cond.wait(state).unwrap();
+        }
+        let writer = state.writer.take().unwrap();
+        if let Some(e) = state.error.take() {
+            return Err(e);
+        }
+        drop(state);
+
+        let file = writer.finish()?.into_inner().map_err(|e| io_error(e.into_error()))?;
+        file.as_file().sync_all().map_err(io_error)?;
+        file.persist(path).map_err(|e| io_error(e.error))?;
+        Ok(())
+    }
+}
+
+impl Drop for Paused<'_> {
+    fn drop(&mut self) {
+        let pause = &self.walker.shared.pause;
+        let mut state = pause.state.lock().unwrap();
+        state.requested = false;
+        pause.requested.store(false, Ordering::Release);
+        pause.cond.notify_all();
+    }
+}
+
+struct ChannelVisitor {
+    tx: SyncSender<Result<DirEntry>>,
+}
+
+impl Visitor for ChannelVisitor {
+    fn visit(&self, entry: &DirEntry) -> Visit {
+        if self.tx.send(Ok(entry.clone())).is_ok() {
+            Visit::Continue
+        } else {
+            Visit::Quit
+        }
+    }
+
+    fn error(&self, err: Error) -> Visit {
+        if self.tx.send(Err(err)).is_ok() {
+            Visit::Continue
+        } else {
+            Visit::Quit
+        }
+    }
+}
+
+impl Iterator for BfsWalker {
+    type Item = Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.rx.as_ref()?.recv().ok()?;
+        if entry.is_ok() {
+            self.shared.yielded.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(entry)
+    }
+}
+
+impl Drop for BfsWalker {
+    fn drop(&mut self) {
+        self.shared.quit.store(true, Ordering::Release);
+        self.rx.take();
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The directories waiting to be read by one worker.
+///
+/// Other workers steal from the front of the deque. What does not fit, in the deque or in the memory budget, goes to
+/// the overflow queue, which spills to disk and is only used by the owner. Once the overflow is in use new entries go
+/// there too, so they stay behind the ones in the deque, and the deque is refilled from the overflow as it drains.
+struct LocalQueue {
+    deque:      deque::Worker<QueueEntry>,
+    overflow:   PathQueue,
+    memory:     MemoryBudget,
+
+    /// An entry taken from the overflow that did not fit in the memory budget, next in line after the deque.
+    held:       Option<QueueEntry>,
+
+    /// State of the xorshift generator picking the first worker to steal from.
+    rng:        u64,
+}
+
+impl LocalQueue {
+    fn new(deque: deque::Worker<QueueEntry>, overflow: PathQueue, memory: MemoryBudget) -> Self {
+        memory.reserve((deque.capacity() * size_of::<QueueEntry>()) as u64);
+        // Any nonzero seed will do, as long as the workers differ.
+        let rng = RandomState::new().hash_one(&deque as *const _ as usize) | 1;
+        Self { deque, overflow, memory, held: None, rng }
+    }
+
+    fn push(&mut self, entry: QueueEntry) -> Result<()> {
+        let entry = if self.overflow.is_empty() && self.held.is_none() {
+            let size = path_queue::heap_size(&entry);
+            if !self.memory.try_reserve(size) {
+                return Ok(self.overflow.push(entry)?);
+            }
+            match self.deque.push(entry) {
+                Ok(()) => return Ok(()),
+                Err(entry) => {
+                    self.memory.release(size);
+                    entry
+                },
+            }
+        } else {
+            entry
+        };
+        Ok(self.overflow.push(entry)?)
+    }
+
+    fn pop(&mut self) -> Result<Option<QueueEntry>> {
+        // Refill at half capacity, which a deque of one slot only reaches when empty.
+        if self.deque.len() <= self.deque.capacity() / 2 {
+            while self.deque.room() > 0 {
+                let entry = match self.held.take() {
+                    Some(entry) => entry,
+                    None => match self.overflow.pop()? {
+                        Some(entry) => entry,
+                        None => break,
+                    },
+                };
+                if !self.memory.try_reserve(path_queue::heap_size(&entry)) {
+                    self.held = Some(entry);
+                    break;
+                }
+                if self.deque.push(entry).is_err() {
+                    unreachable!("only the owner pushes to the deque");
+                }
+            }
+        }
+        match self.deque.pop() {
+            Some(entry) => {
+                self.memory.release(path_queue::heap_size(&entry));
+                Ok(Some(entry))
+            },
+            None => Ok(self.held.take()),
+        }
+    }
+
+    /// Number of directories queued.
+    fn len(&self) -> usize {
+        self.deque.len() + self.held.is_some() as usize + self.overflow.len()
+    }
+
+    /// Writes the queued directories to a checkpoint, keeping them queued. The deque is emptied and refilled in the
+    /// same order, which is safe as nobody steals while the workers are paused; the held entry and the overflow are
+    /// written where they are.
+    fn write_to<W: Write>(&mut self, writer: &mut EntryWriter<W>) -> Result<()> {
+        let entries: Vec<_> = std::iter::from_fn(|| self.deque.pop()).collect();
+        let mut result = Ok(());
+        for entry in entries {
+            if result.is_ok() {
+                result = writer.push(&entry);
+            }
+            if self.deque.push(entry).is_err() {
+                unreachable!("the deque held the entry a moment ago");
+            }
+        }
+        result?;
+        if let Some(entry) = &self.held {
+            writer.push(entry)?;
+        }
+        self.overflow.write_to(writer)?;
+        Ok(())
+    }
+
+    /// Steals half of the directories in the deque of another worker and returns the first one.
+    fn steal_from(&mut self, victim: &deque::Stealer<QueueEntry>) -> Option<QueueEntry> {
+        victim.steal_into(&mut self.deque).inspect(|entry| self.memory.release(path_queue::heap_size(entry)))
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
+
+impl Drop for LocalQueue {
+    fn drop(&mut self) {
+        while let Some(entry) = self.deque.pop() {
+            self.memory.release(path_queue::heap_size(&entry));
+        }
+        self.memory.release((self.deque.capacity() * size_of::<QueueEntry>()) as u64);
+    }
+}
+
+fn pop_or_steal(shared: &Shared, index: usize, local: &mut LocalQueue) -> Result<Option<QueueEntry>> {
+    if let Some(dir) = local.pop()? {
+        return Ok(Some(dir));
+    }
+    // Start at a random worker so that idle workers do not all go for the same victims.
+    let n = shared.stealers.len();
+    let start = local.next_random() as usize % n;
+    let dir = (0..n).map(|i| (start + i) % n)
+        .filter(|&i| i != index)
+        .find_map(|i| local.steal_from(&shared.stealers[i]));
+    if dir.is_some() {
+        shared.steals.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(dir)
+}
+
+#[cfg(unix)]
+fn dev_ino(metadata: &fs::Metadata) -> (u64, u64) {
+    (metadata.dev(), metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn dev_ino(_metadata: &fs::Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+#[cfg(unix)]
+fn entry_ino(entry: &fs::DirEntry) -> u64 {
+    entry.ino()
+}
+
+#[cfg(not(unix))]
+fn entry_ino(_entry: &fs::DirEntry) -> u64 {
+    0
+}
+
+fn breadth_first_traverse(shared: &Shared, index: usize, local: &mut LocalQueue, visitor: &dyn Visitor) -> Result<()> {
+    let opt = &shared.opts;
+    let counter = &shared.counter;
+    // Reports an error to the visitor, returning false if it asks to quit.
+    let report = |err| {
+        if visitor.error(err) == Visit::Quit {
+            shared.quit.store(true, Ordering::Release);
+            false
+        } else {
+            true
+        }
+    };
+    let times = &shared.times[index];
+    let mut since = Instant::now();
+    while !shared.quit.load(Ordering::Acquire) {
+        if shared.pause.requested.load(Ordering::Acquire) {
+            shared.pause.wait(local);
+            ThreadTimes::add(&times.sleeping, &mut since);
+            continue;
+        }
+        if let Some(dir) = pop_or_steal(shared, index, local)? {
+            shared.reading.fetch_add(1, Ordering::Release);
+            shared.depth.fetch_max(dir.depth as usize, Ordering::Relaxed);
+            let path = dir.path;
+            let depth = dir.depth as usize + 1;
+            match fs::read_dir(&path) {
+                Ok(entries) => {
+                    shared.dirs_read.fetch_add(1, Ordering::Relaxed);
+                    let mut seen = 0;
+                    for entry in entries {
+                        if shared.quit.load(Ordering::Acquire) {
+                            return Ok(());
+                        }
+                        seen += 1;
+                        let entry = match entry {
+                            Ok(entry) => entry,
+                            Err(e) => {
+                                if !report(Error::Io { path: path.clone(), source: e }) {
+                                    return Ok(());
+                                }
+                                continue;
+                            }
+                        };
+                        if opt.is_skipped(&entry.file_name()) {
+                            continue;
+                        }
+                        let path = entry.path();
+                        let file_type = match entry.file_type() {
+                            Ok(t) => t,
+                            Err(e) => {
+                                if !report(Error::Io { path, source: e }) {
+                                    return Ok(());
+                                }
+                                continue;
+                            }
+                        };
+                        let is_link = file_type.is_symlink();
+                        // Followed links need the type of their target, and directories their device to stay on one
+                        // file system and detect loops.
+                        let metadata = OnceLock::new();
+                        let needs_stat = (file_type.is_symlink() && opt.follow_links)
+                            || (file_type.is_dir() && (opt.same_file_system || opt.follow_links));
+                        let file_type = if needs_stat {
+                            let m = if opt.follow_links { fs::metadata(&path) } else { fs::symlink_metadata(&path) };
+                            match m {
+                                Ok(m) => metadata.get_or_init(|| m).file_type(),
+                                Err(e) => {
+                                    if !report(Error::Io { path, source: e }) {
+                                        return Ok(());
+                                    }
+                                    continue;
+                                }
+                            }
+                        } else {
+                            file_type
+                        };
+                        let (dev, ino) = metadata.get().map_or((dir.dev, entry_ino(&entry)), dev_ino);
+                        let entry = DirEntry {
+                            path,
+                            depth,
+                            root: dir.root as usize,
+                            dev,
+                            ino,
+                            file_type,
+                            follow_link: opt.follow_links,
+                            is_link,
+                            metadata,
+                            link_target: OnceLock::new(),
+                            extension: OnceLock::new(),
+                        };
+                        let visit = if shared.filters.iter().all(|filter| filter(&entry)) {
+                            // Errors are left for the consumer to get again.
+                            if opt.prefetch {
+                                let _ = entry.metadata();
+                                if is_link {
+                                    let _ = entry.read_link();
+                                }
+                            }
+                            visitor.visit(&entry)
+                        } else {
+                            Visit::Continue
+                        };
+                        match visit {
+                            Visit::Continue if file_type.is_dir() && depth < opt.max_depth => {
+                                if opt.same_file_system && dev != shared.root_devs[dir.root as usize] {
+                                    continue;
+                                }
+                                if opt.follow_links && !shared.visited.lock().unwrap().insert((dev, ino)) {
+                                    if !report(Error::Loop { path: entry.path }) {
+                                        return Ok(());
+                                    }
+                                    continue;
+                                }
+                                local.push(QueueEntry {
+                                    path: entry.path,
+                                    depth: depth as u32,
+                                    root: dir.root,
+                                    dev,
+                                    ino,
+                                    kind: file_type.into(),
+                                })?;
+                                counter.fetch_add(1, Ordering::Release);
+                            },
+                            Visit::Continue | Visit::Skip => {},
+                            Visit::Quit => {
+                                shared.quit.store(true, Ordering::Release);
+                                return Ok(());
+                            },
+                        }
+                    }
+                    shared.entries_seen.fetch_add(seen, Ordering::Relaxed);
+                    if seen > 0 {
+                        shared.max_depth.fetch_max(depth, Ordering::Relaxed);
+                    }
+                },
+                Err(e) => {
+                    if !report(Error::Io { path, source: e }) {
+                        return Ok(());
+                    }
+                },
+            }
+            counter.fetch_sub(1, Ordering::Release);
+            shared.reading.fetch_sub(1, Ordering::Release);
+            ThreadTimes::add(&times.working, &mut since);
+        } else if counter.load(Ordering::Acquire) == 0 {
+            break;
+        } else {
+            thread::sleep(Duration::from_millis(7));
+            ThreadTimes::add(&times.sleeping, &mut since);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn make_tree() -> io::Result<tempfile::TempDir> {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir()?;
+        for dir in ["a/b/c", "a/.hidden", "d/target/e"] {
+            fs::create_dir_all(root.path().join(dir))?;
+        }
+        for file in ["f", "a/g", "a/b/h", "a/b/c/i", "d/target/j"] {
+            File::create(root.path().join(file))?;
+        }
+        Ok(root)
+    }
+
+    fn relative_paths(root: &Path, walker: BfsWalker) -> Result<Vec<String>> {
+        walker.map(|entry| {
+            Ok(entry?.path().strip_prefix(root).unwrap().to_str().unwrap().to_string())
+        }).collect()
+    }
+
+    #[test]
+    fn single_thread_is_breadth_first() -> Result<()> {
+        let root = make_tree().unwrap();
+        let walker = WalkBuilder::new(root.path()).threads(1).build()?;
+        let paths = relative_paths(root.path(), walker)?;
+        let depths: Vec<_> = paths.iter().map(|p| Path::new(p).components().count()).collect();
+        assert!(depths.windows(2).all(|w| w[0] <= w[1]), "{:?}", paths);
+        let mut paths = paths;
+        paths.sort();
+        assert_eq!(paths, ["a", "a/b", "a/b/c", "a/b/c/i", "a/b/h", "a/g", "d", "d/target", "d/target/e", "d/target/j", "f"]);
+        Ok(())
+    }
+
+    #[test]
+    fn hidden_ignore_and_filter() -> Result<()> {
+        let root = make_tree().unwrap();
+        let walker = WalkBuilder::new(root.path())
+            .hidden(true)
+            .ignore(["target", "b"])
+            .filter(|entry| entry.file_type().is_dir())
+            .build()?;
+        let mut paths = relative_paths(root.path(), walker)?;
+        paths.sort();
+        assert_eq!(paths, ["a", "a/.hidden", "d"]);
+        Ok(())
+    }
+
+    #[test]
+    fn visitor_skip_and_depth() -> Result<()> {
+        let root = make_tree().unwrap();
+        let prefix = root.path().to_path_buf();
+        let visited = std::sync::Mutex::new(Vec::new());
+        WalkBuilder::new(root.path()).visit(|entry: &DirEntry| {
+            let path = entry.path().strip_prefix(&prefix).unwrap().to_str().unwrap().to_string();
+            assert_eq!(entry.depth(), Path::new(&path).components().count());
+            assert_eq!(entry.metadata().unwrap().is_dir(), entry.file_type().is_dir());
+            let skip = path == "a/b";
+            visited.lock().unwrap().push(path);
+            if skip { Visit::Skip } else { Visit::Continue }
+        })?;
+        let mut visited = visited.into_inner().unwrap();
+        visited.sort();
+        assert_eq!(visited, ["a", "a/b", "a/g", "d", "d/target", "d/target/e", "d/target/j", "f"]);
+        Ok(())
+    }
+
+    #[test]
+    fn visitor_quit() -> Result<()> {
+        let root = make_tree().unwrap();
+        let count = AtomicUsize::new(0);
+        WalkBuilder::new(root.path()).threads(1).visit(|_: &DirEntry| {
+            count.fetch_add(1, Ordering::Relaxed);
+            Visit::Quit
+        })?;
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn max_depth() -> Result<()> {
+        let root = make_tree().unwrap();
+        let walker = WalkBuilder::new(root.path()).max_depth(2).build()?;
+        let mut paths = relative_paths(root.path(), walker)?;
+        paths.sort();
+        assert_eq!(paths, ["a", "a/b", "a/g", "d", "d/target", "f"]);
+        Ok(())
+    }
+
+    #[test]
+    fn roots_and_symlink_loops() -> Result<()> {
+        let root = make_tree().unwrap();
+        std::os::unix::fs::symlink("..", root.path().join("a/b/up")).unwrap();
+        let walker = WalkBuilder::new(root.path().join("d"))
+            .add_root(root.path().join("a"))
+            .follow_links(true)
+            .build()?;
+        let mut paths = Vec::new();
+        let mut loops = 0;
+        for entry in walker {
+            match entry {
+                Ok(entry) => {
+                    let rel = entry.path().strip_prefix(root.path()).unwrap().to_str().unwrap().to_string();
+                    assert_eq!(entry.root_index(), if rel.starts_with('d') { 0 } else { 1 });
+                    paths.push(rel);
+                },
+                Err(Error::Loop { .. }) => loops += 1,
+                Err(e) => return Err(e),
+            }
+        }
+        paths.sort();
+        assert_eq!(paths, ["a/b", "a/b/c", "a/b/c/i", "a/b/h", "a/b/up", "a/g", "d/target", "d/target/e", "d/target/j"]);
+        assert_eq!(loops, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn memory_budget() -> Result<()> {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        for i in 0..200 {
+            fs::create_dir_all(root.path().join(format!("{:03}/sub", i))).unwrap();
+        }
+        let budget = MemoryBudget::new(4096);
+        let walker = WalkBuilder::new(root.path()).threads(2).memory_budget(budget.clone()).build()?;
+        assert_eq!(walker.count(), 400);
+        assert!(budget.peak() <= budget.limit(), "{} > {}", budget.peak(), budget.limit());
+        assert_eq!(budget.used(), 0);
+        Ok(())
+    }
+
+    fn entry(i: u64) -> QueueEntry {
+        QueueEntry {
+            path: PathBuf::from(format!("{:0100}", i)),
+            depth: 1,
+            root: 0,
+            dev: 0,
+            ino: i,
+            kind: path_queue::FileKind::Dir,
+        }
+    }
+
+    #[test]
+    fn local_queue_budget() -> Result<()> {
+        let (deque, _stealer) = deque::new(64);
+        let ring = (deque.capacity() * size_of::<QueueEntry>()) as u64;
+        let budget = MemoryBudget::new(ring + 1000);
+        let mut spill = SpillOptions::default();
+        spill.memory = budget.clone();
+        let overflow = PathQueue::with_spill_options(4, 4, spill)?;
+        let mut local = LocalQueue::new(deque, overflow, budget.clone());
+        let rings = budget.used();
+        // Each path takes at least 100 bytes, so far fewer than 40 fit in the budget.
+        for i in 0..40 {
+            local.push(entry(i))?;
+        }
+        assert!(budget.peak() <= budget.limit(), "{} > {}", budget.peak(), budget.limit());
+        let mut popped = Vec::new();
+        while let Some(entry) = local.pop()? {
+            assert!(budget.peak() <= budget.limit(), "{} > {}", budget.peak(), budget.limit());
+            popped.push(entry.ino);
+        }
+        assert_eq!(popped, (0..40).collect::<Vec<_>>());
+        assert_eq!(budget.used(), rings);
+        Ok(())
+    }
+
+    #[test]
+    fn tiny_memory_budget() -> Result<()> {
+        let root = make_tree().unwrap();
+        let walker = WalkBuilder::new(root.path()).threads(2).memory_budget(MemoryBudget::new(1)).build()?;
+        assert_eq!(walker.count(), 11);
+        Ok(())
+    }
+
+    #[test]
+    fn local_queue_write_to() -> Result<()> {
+        let (deque, _stealer) = deque::new(64);
+        let ring = (deque.capacity() * size_of::<QueueEntry>()) as u64;
+        let budget = MemoryBudget::new(ring + 1000);
+        let mut spill = SpillOptions::default();
+        spill.memory = budget.clone();
+        let overflow = PathQueue::with_spill_options(4, 4, spill.clone())?;
+        let mut local = LocalQueue::new(deque, overflow, budget);
+        for i in 0..20000 {
+            local.push(entry(i))?;
+        }
+        for _ in 0..100 {
+            local.pop()?;
+        }
+        let (written, read) = (spill.bytes_written(), spill.bytes_read());
+        assert!(written > 0);
+
+        // Writing the queue neither moves entries to or from the spill files nor changes their order.
+        let mut writer = EntryWriter::new(Vec::new(), Compression::None)?;
+        local.write_to(&mut writer)?;
+        let data = writer.finish()?;
+        assert_eq!((spill.bytes_written(), spill.bytes_read()), (written, read));
+        let saved: Vec<_> = EntryReader::new(&data[..]).map(|e| e.map(|e| e.ino)).collect::<path_queue::Result<_>>()?;
+        let mut popped = Vec::new();
+        while let Some(entry) = local.pop()? {
+            popped.push(entry.ino);
+        }
+        assert_eq!(saved, (100..20000).collect::<Vec<_>>());
+        assert_eq!(popped, saved);
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint_after_failure() -> Result<()> {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        for i in 0..20000 {
+            fs::create_dir(root.path().join(format!("{:05}", i))).unwrap();
+        }
+        let dir = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        // The queue spills most of the root's subdirectories and fails on the first block written, dropping them.
+        let mut walker = WalkBuilder::new(root.path())
+            .threads(1)
+            .memory_budget(MemoryBudget::new(64 << 10))
+            .max_spill(1)
+            .build()?;
+        assert!(walker.by_ref().any(|entry| entry.is_err()));
+        let result = walker.pause(|_| {}).checkpoint(dir.path().join("checkpoint"));
+        assert!(matches!(result, Err(Error::CheckpointIncomplete { .. })), "{:?}", result);
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint_and_resume() -> Result<()> {
+        let root = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        for i in 0..100 {
+            fs::create_dir_all(root.path().join(format!("{:03}/sub/sub", i))).unwrap();
+        }
+        let dir = tempfile::Builder::new().prefix("bfind").tempdir().unwrap();
+        let checkpoint = dir.path().join("checkpoint");
+        let mut walker = WalkBuilder::new(root.path()).threads(3).build()?;
+        let mut first = Vec::new();
+        for entry in walker.by_ref().take(50) {
+            first.push(entry?.into_path());
+        }
+        walker.pause(|entry| first.push(entry.unwrap().into_path())).checkpoint(&checkpoint)?;
+        assert_eq!(walker.yielded(), first.len() as u64);
+        drop(walker);
+
+        let mut walker = WalkBuilder::new(root.path()).threads(3).resume(&checkpoint).build()?;
+        assert_eq!(walker.yielded(), first.len() as u64);
+        let mut paths = first;
+        for entry in walker.by_ref() {
+            paths.push(entry?.into_path());
+        }
+        assert_eq!(walker.yielded(), 300);
+        let len = paths.len();
+        paths.sort();
+        paths.dedup();
+        assert_eq!((len, paths.len()), (300, 300));
+
+        let other = WalkBuilder::new(root.path().join("000")).resume(&checkpoint).build();
+        assert!(matches!(other, Err(Error::CheckpointMismatch { .. })));
+        fs::write(&checkpoint, "garbage").unwrap();
+        let invalid = WalkBuilder::new(root.path()).resume(&checkpoint).build();
+        assert!(matches!(invalid, Err(Error::InvalidCheckpoint { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn progress_and_stats() -> Result<()> {
+        let root = make_tree().unwrap();
+        let mut walker = WalkBuilder::new(root.path()).threads(2).build()?;
+        let monitor = walker.monitor();
+        assert_eq!(walker.by_ref().count(), 11);
+        let progress = Progress {
+            dirs_read: 7,
+            entries_seen: 12,
+            yielded: 11,
+            depth: 3,
+            queued: 0,
+            spilled: 0,
+        };
+        assert_eq!(monitor.progress(), progress);
+        let stats = monitor.stats();
+        assert_eq!((stats.progress, stats.max_depth, stats.spill_bytes_written), (progress, 4, 0));
+        assert_eq!(stats.threads.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn early_drop_stops_workers() -> Result<()> {
+        let root = make_tree().unwrap();
+        let mut walker = WalkBuilder::new(root.path()).threads(4).build()?;
+        assert!(walker.next().is_some());
+        drop(walker);
+        Ok(())
+    }
+}